@@ -0,0 +1,49 @@
+use warp::http::Method;
+use warp::Filter;
+
+use crate::backend::Client;
+use crate::basic::{handle_rejection, with_base, within_limits};
+use crate::metrics;
+
+const GET_METHOD: Method = warp::http::Method::GET;
+
+pub fn admin_endpoint(client: Client) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let scrub_endpoint = metrics::instrument(
+        "scrub",
+        warp::any()
+            .and(with_base(client.clone(), &GET_METHOD))
+            .and(warp::path("scrub"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(within_limits())
+            .and_then(crate::backend::get_scrub_reports),
+    );
+
+    let audit_endpoint = metrics::instrument(
+        "audit",
+        warp::any()
+            .and(with_base(client, &GET_METHOD))
+            .and(warp::path("audit"))
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::<crate::backend::audit::AuditQuery>())
+            .and(within_limits())
+            .and_then(crate::backend::get_audit_log),
+    );
+
+    scrub_endpoint
+        .or(audit_endpoint)
+        .recover(handle_rejection)
+        .boxed()
+}
+
+/// `/metrics` on the public address, only for the admin
+pub fn metrics_endpoint(client: Client) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_base(client, &GET_METHOD))
+        .and(within_limits())
+        .and_then(crate::backend::get_metrics)
+        .boxed()
+}
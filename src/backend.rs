@@ -1,14 +1,48 @@
+use crate::basic::auth::Auth;
+use crate::metrics::{self, time_operation};
+use crate::rate_limit::{Limits, RateLimit};
 use crate::GeneralResult;
+use futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use warp::filters::path::Tail;
+use warp::reply::Response;
 use warp::Rejection;
 
+pub mod audit;
+pub mod checksum;
+pub mod compression;
+pub mod content_type;
+pub mod cors;
+pub mod encryption;
+pub mod error;
+pub mod events;
+pub mod form;
+pub mod lifecycle;
 pub mod mongodb;
+pub mod policy;
+pub mod post_policy;
+pub mod quota;
+pub mod range;
 pub mod types;
+pub mod webhooks;
 
 use crate::Context;
 
-use types::{CreateBucketResult, CreateObjectResult, DeleteBucketResult, DeleteObjectResult};
+use audit::{AuditEntry, AuditQuery};
+use checksum::ExpectedChecksums;
+use compression::Compression;
+use cors::CorsRule;
+use error::ApiError;
+use lifecycle::LifecycleRules;
+use policy::ObjectPolicy;
+use post_policy::PostPolicy;
+use quota::Quota;
+use types::{
+    CreateBucketResult, CreateObjectResult, CreateObjectValidationError, CreateWebhookResult,
+    FormUploadResult,
+};
+use webhooks::WebhookRequest;
 
 pub use self::mongodb as implementation;
 
@@ -17,6 +51,124 @@ pub struct DeleteBucketOptions {
     purge: Option<bool>,
 }
 
+/// most objects one batch delete removes
+pub const MAX_BATCH_DELETE: usize = 1000;
+
+/// body of `POST /<bucket>?delete`, the names of the objects or a prefix they share
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteObjectsRequest {
+    #[serde(default)]
+    pub objects: Vec<String>,
+    /// removes up to `MAX_BATCH_DELETE` objects starting with it, an empty prefix matches all
+    pub prefix: Option<String>,
+}
+
+impl DeleteObjectsRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.prefix, self.objects.len()) {
+            (Some(_), 0) => Ok(()),
+            (Some(_), _) => Err(String::from("objects and prefix can not be combined")),
+            (None, 0) => Err(String::from("objects or prefix is required")),
+            (None, n) if n > MAX_BATCH_DELETE => Err(format!(
+                "at most {} objects can be deleted at once",
+                MAX_BATCH_DELETE
+            )),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookOptions {
+    id: String,
+}
+
+pub const CUSTOMER_KEY_HEADER: &str = "x-server-side-encryption-customer-key";
+
+/// request headers used when uploading an object
+#[derive(Debug, Default)]
+pub struct UploadHeaders {
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    pub checksums: ExpectedChecksums,
+    /// base64 encoded key to encrypt the object with, it is never stored
+    pub customer_key: Option<String>,
+    /// when the object is deleted by the sweeper, from the `x-expires-at` header
+    pub expires_at: Option<String>,
+    /// bounds of the object length from the post policy of a form upload
+    pub min_length: Option<u64>,
+    pub max_length: Option<u64>,
+}
+
+impl UploadHeaders {
+    pub fn from_headers(headers: warp::http::HeaderMap) -> UploadHeaders {
+        let get = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        UploadHeaders {
+            content_type: get("content-type"),
+            content_length: get("content-length").and_then(|value| value.parse().ok()),
+            checksums: ExpectedChecksums {
+                md5: get("content-md5"),
+                sha256: get("x-checksum-sha256"),
+                crc32c: get("x-checksum-crc32c"),
+            },
+            customer_key: get(CUSTOMER_KEY_HEADER),
+            expires_at: get("x-expires-at"),
+            ..Default::default()
+        }
+    }
+}
+
+/// request headers that affect how a stored object is returned
+#[derive(Debug, Default)]
+pub struct DownloadHeaders {
+    pub accept_encoding: Option<String>,
+    /// a single range of bytes is served, see `range::parse`
+    pub range: Option<String>,
+    /// base64 encoded key the object was encrypted with
+    pub customer_key: Option<String>,
+}
+
+impl DownloadHeaders {
+    pub fn from_headers(headers: warp::http::HeaderMap) -> DownloadHeaders {
+        let get = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        DownloadHeaders {
+            accept_encoding: get("accept-encoding"),
+            range: get("range"),
+            customer_key: get(CUSTOMER_KEY_HEADER),
+        }
+    }
+}
+
+/// settings that can be given as json body when creating a bucket
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BucketSettings {
+    pub quota: Quota,
+    pub policy: ObjectPolicy,
+    /// store identical content only once per organisation
+    pub deduplicate: bool,
+    /// compress objects before they are stored
+    pub compression: Option<Compression>,
+    /// encrypt objects with a data key wrapped by the master key
+    pub encrypt: bool,
+    pub lifecycle: LifecycleRules,
+    /// rules for browsers on other origins, the server wide default applies when empty
+    pub cors: Vec<CorsRule>,
+}
+
 pub type Client = implementation::Client;
 
 pub const EMPTY_ORGANISATION: &str = "general";
@@ -26,12 +178,16 @@ fn empty_organisation() -> String {
     String::from(EMPTY_ORGANISATION)
 }
 
-#[derive(Debug, Serialize, Deserialize, zeroize::Zeroize, zeroize::ZeroizeOnDrop)]
+#[derive(Debug, Clone, Serialize, Deserialize, zeroize::Zeroize, zeroize::ZeroizeOnDrop)]
 pub struct KeyPair {
     access: String,
     secret: String,
     #[serde(default = "empty_organisation")]
     organisation_id: String,
+    /// the configured default applies when missing
+    #[serde(default)]
+    #[zeroize(skip)]
+    rate_limit: Option<RateLimit>,
 }
 
 impl KeyPair {
@@ -40,6 +196,7 @@ impl KeyPair {
             access,
             secret,
             organisation_id,
+            rate_limit: None,
         }
     }
 
@@ -54,90 +211,624 @@ impl KeyPair {
     pub fn organisation_id(&self) -> &str {
         &self.organisation_id
     }
+
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
 }
 
-#[derive(Debug)]
-pub struct Unauthorised {
-    pub reason: String,
+/// returns the entry recording the outcome in the audit log,
+/// the rate limits of the caller were already checked when the context was created
+fn check_auth(context: &Context) -> Result<AuditEntry, Rejection> {
+    if context.validate_request() {
+        Ok(AuditEntry::new(context))
+    } else {
+        metrics::auth_failure(if context.is_logged_in() {
+            "path_not_allowed"
+        } else {
+            "anonymous_not_allowed"
+        });
+        Err(ApiError::Unauthorised(format!(
+            "Unauthorised for path {} {}",
+            context.method, context.path
+        ))
+        .reject())
+    }
 }
 
-impl warp::reject::Reject for Unauthorised {}
+/// the audit entry of a request covering many paths, each of them is authorised on its own,
+/// anonymous requests are allowed or not regardless of the path
+fn check_batch(context: &Context) -> Result<AuditEntry, Rejection> {
+    if !context.is_logged_in() {
+        return check_auth(context);
+    }
 
-fn check_auth(context: &Context) -> Result<(), Rejection> {
-    if context.validate_request() {
-        Ok(())
+    Ok(AuditEntry::new(context))
+}
+
+fn check_admin(context: &Context) -> Result<AuditEntry, Rejection> {
+    let audit = check_auth(context)?;
+
+    if context.is_logged_in() && context.organisation_id() == ADMIN_ORGANISATION {
+        Ok(audit)
     } else {
-        Err(warp::reject::custom(Unauthorised {
-            reason: format!("Unauthorised for path {} {}", context.method, context.path),
-        }))
+        metrics::auth_failure("not_admin");
+        Err(ApiError::Unauthorised(format!(
+            "Admin only path {} {}",
+            context.method, context.path
+        ))
+        .reject())
+    }
+}
+
+/// for operations anonymous requests are never allowed, even if the auth mode is optional
+fn check_logged_in(context: &Context) -> Result<AuditEntry, Rejection> {
+    let audit = check_auth(context)?;
+
+    if context.is_logged_in() {
+        Ok(audit)
+    } else {
+        metrics::auth_failure("not_logged_in");
+        Err(ApiError::Unauthorised(format!(
+            "Login required for {} {}",
+            context.method, context.path
+        ))
+        .reject())
     }
 }
 
 pub async fn setup(client: &Client) -> GeneralResult<()> {
-    implementation::setup(client).await
+    implementation::setup(client).await?;
+    webhooks::setup(client.clone());
+    audit::setup(client.clone())?;
+    events::setup().await
 }
 
 pub async fn make_client() -> GeneralResult<Client> {
     implementation::make_client().await
 }
 
+/// checks that the backend still answers
+pub async fn ping(client: &Client) -> GeneralResult<()> {
+    time_operation("ping", implementation::ping(client)).await
+}
+
+/// bucket settings and webhooks are sent as json, an empty body needs no content type
+fn check_json_content_type(content_type: Option<&str>, body: &[u8]) -> Result<(), ApiError> {
+    if body.is_empty() {
+        return Ok(());
+    }
+
+    match content_type.map(content_type::essence) {
+        None => Err(ApiError::MissingContentType),
+        Some(essence) if essence == "application/json" => Ok(()),
+        Some(essence) => Err(ApiError::UnsupportedMediaType(format!(
+            "expected application/json, got {}",
+            essence
+        ))),
+    }
+}
+
 pub async fn create_bucket(
     mut context: Context,
     bucket_name: String,
-) -> Result<CreateBucketResult, Rejection> {
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+) -> Result<Response, Rejection> {
     context.path = bucket_name.to_string();
-    check_auth(&context)?;
-    implementation::create_bucket(context, bucket_name).await
+    let audit = check_auth(&context)?;
+
+    if let Err(error) = check_json_content_type(content_type.as_deref(), &body) {
+        return audit.finish(Ok(
+            error.with_fields(serde_json::json!({"bucket": bucket_name}))
+        ));
+    }
+
+    let settings = if body.is_empty() {
+        BucketSettings::default()
+    } else {
+        match serde_json::from_slice::<BucketSettings>(&body) {
+            Ok(settings) => settings,
+            Err(e) => {
+                return audit.finish(Ok(CreateBucketResult {
+                    bucket: bucket_name,
+                    created: false,
+                    validation_error: Some(format!("invalid bucket settings, {}", e)),
+                }))
+            }
+        }
+    };
+
+    if let Err(e) = settings.cors.iter().try_for_each(CorsRule::validate) {
+        return audit.finish(Ok(CreateBucketResult {
+            bucket: bucket_name,
+            created: false,
+            validation_error: Some(format!("invalid bucket settings, cors: {}", e)),
+        }));
+    }
+
+    if settings.encrypt {
+        if let Err(e) = encryption::EncryptionKey::master() {
+            return audit.finish(Ok(CreateBucketResult {
+                bucket: bucket_name,
+                created: false,
+                validation_error: Some(format!("invalid bucket settings, {}", e)),
+            }));
+        }
+    }
+
+    audit.finish(
+        time_operation(
+            "create_bucket",
+            implementation::create_bucket(context, bucket_name, settings),
+        )
+        .await,
+    )
 }
 
 pub async fn delete_bucket(
     mut context: Context,
     bucket_name: String,
     options: DeleteBucketOptions,
-) -> Result<DeleteBucketResult, Rejection> {
+) -> Result<Response, Rejection> {
     context.path = bucket_name.to_string();
-    check_auth(&context)?;
-    implementation::delete_bucket(context, bucket_name, options).await
+    let audit = check_auth(&context)?;
+    audit.finish(
+        time_operation(
+            "delete_bucket",
+            implementation::delete_bucket(context, bucket_name, options),
+        )
+        .await,
+    )
 }
 
 pub async fn create_object(
     mut context: Context,
     bucket_name: String,
     object_name: Tail,
-    content_type: String,
+    headers: UploadHeaders,
     buffer: impl futures::Stream<Item = Result<impl warp::Buf, warp::Error>>,
-) -> Result<CreateObjectResult, Rejection> {
+) -> Result<Response, Rejection> {
     let object_name = object_name.as_str().to_string();
     context.path = format!("{}/{}", bucket_name, object_name);
-    check_auth(&context)?;
-    implementation::create_object(context, bucket_name, object_name, content_type, buffer).await
+    let audit = check_auth(&context)?;
+    let buffer = audit.count_upload(metrics::count_upload(context.limits.count_upload(buffer)));
+    audit.finish(
+        time_operation(
+            "create_object",
+            implementation::create_object(&context, bucket_name, object_name, headers, buffer),
+        )
+        .await,
+    )
+}
+
+/// stores every file of a `multipart/form-data` body as its own object, a form without
+/// credentials can carry a post policy signed by a keypair instead,
+/// a token has to allow `POST <bucket>/<object>` of each file
+pub async fn create_objects_from_form(
+    mut context: Context,
+    bucket_name: String,
+    content_type: String,
+    body: impl futures::Stream<Item = Result<impl warp::Buf + Send + 'static, warp::Error>>
+        + Send
+        + 'static,
+) -> Result<Response, Rejection> {
+    context.path = bucket_name.to_string();
+    let body =
+        metrics::count_upload(body).map_ok(|mut chunk| chunk.copy_to_bytes(chunk.remaining()));
+    let mut form = form::parse(&content_type, body)?;
+    let (fields, file) = form::read_fields(&mut form).await?;
+
+    let policy = match fields.get("policy") {
+        Some(policy) if !context.is_logged_in() => {
+            let (auth, policy) = Auth::from_policy(
+                &context.client,
+                fields.get("access_key").map(String::as_str),
+                policy,
+                fields.get("signature").map(String::as_str),
+            )
+            .await
+            .map_err(|e| ApiError::Unauthorised(e).reject())?;
+            // the client ip was counted before the form was read, now the keypair is
+            context.limits =
+                Limits::resolve(&context.client, Some(&auth), context.remote_addr).await;
+            context.limits.check_again()?;
+            context.auth = Some(auth);
+            Some(policy)
+        }
+        _ => None,
+    };
+    // a policy allows the bucket, anything else is authorised for every file on its own
+    let audit = if policy.is_some() {
+        check_auth(&context)?
+    } else {
+        check_batch(&context)?
+    };
+
+    let result = time_operation(
+        "create_objects_from_form",
+        create_form_objects(&context, &audit, bucket_name, fields, policy, file, form),
+    )
+    .await;
+    audit.finish(result)
+}
+
+async fn create_form_objects(
+    context: &Context,
+    audit: &AuditEntry,
+    bucket_name: String,
+    fields: HashMap<String, String>,
+    policy: Option<PostPolicy>,
+    mut file: Option<multer::Field<'static>>,
+    mut form: multer::Multipart<'static>,
+) -> Result<FormUploadResult, Rejection> {
+    let mut objects = Vec::new();
+    while let Some(field) = file.take() {
+        // the field is consumed before the next one can be read
+        if let Some(object) = create_form_object(
+            context,
+            audit,
+            &bucket_name,
+            &fields,
+            policy.as_ref(),
+            field,
+        )
+        .await?
+        {
+            objects.push(object);
+        }
+        file = form::next_field(&mut form).await?;
+    }
+
+    Ok(FormUploadResult {
+        bucket: bucket_name,
+        objects,
+    })
+}
+
+/// text fields after the files and empty file inputs are skipped
+async fn create_form_object(
+    context: &Context,
+    audit: &AuditEntry,
+    bucket_name: &str,
+    fields: &HashMap<String, String>,
+    policy: Option<&PostPolicy>,
+    field: multer::Field<'static>,
+) -> Result<Option<CreateObjectResult>, Rejection> {
+    let filename = match field.file_name() {
+        Some(filename) if form::is_file(&field) && !filename.is_empty() => filename.to_string(),
+        _ => return Ok(None),
+    };
+    let object_name = form::object_name(fields, &filename);
+    let content_type = field.content_type().map(ToString::to_string);
+
+    if policy.is_none() {
+        let path = format!("{}/{}", bucket_name, object_name);
+        if !context.validate_path(&warp::http::Method::POST, &path) {
+            metrics::auth_failure("path_not_allowed");
+            return Ok(Some(CreateObjectResult::rejected(
+                bucket_name.to_string(),
+                object_name,
+                CreateObjectValidationError::Unauthorised(format!(
+                    "Unauthorised for path POST {}",
+                    path
+                )),
+            )));
+        }
+    }
+
+    let (min_length, max_length) = policy.map_or((None, None), PostPolicy::length_range);
+    if let Some(policy) = policy {
+        let values =
+            form::policy_fields(fields, bucket_name, &object_name, content_type.as_deref());
+        if let Err(validation_error) = policy.check(&values) {
+            return Ok(Some(CreateObjectResult::rejected(
+                bucket_name.to_string(),
+                object_name,
+                validation_error,
+            )));
+        }
+    }
+
+    let headers = UploadHeaders {
+        content_type,
+        min_length,
+        max_length,
+        ..Default::default()
+    };
+    let buffer = audit.count_upload(context.limits.count_upload(field));
+    implementation::create_object(
+        context,
+        bucket_name.to_string(),
+        object_name,
+        headers,
+        buffer,
+    )
+    .await
+    .map(Some)
 }
 
 pub async fn get_object(
     mut context: Context,
     bucket_name: String,
     object_name: Tail,
-) -> Result<warp::reply::Response, Rejection> {
+    headers: DownloadHeaders,
+) -> Result<Response, Rejection> {
+    let object_name = object_name.as_str().to_string();
+    context.path = format!("{}/{}", bucket_name, object_name);
+    let audit = check_auth(&context)?;
+    let limits = context.limits.clone();
+    audit.finish(
+        time_operation(
+            "get_object",
+            implementation::get_object(context, bucket_name, object_name, headers),
+        )
+        .await
+        .map(metrics::count_download)
+        .map(|response| limits.count_download(response)),
+    )
+}
+
+pub async fn head_object(
+    mut context: Context,
+    bucket_name: String,
+    object_name: Tail,
+    headers: DownloadHeaders,
+) -> Result<Response, Rejection> {
     let object_name = object_name.as_str().to_string();
     context.path = format!("{}/{}", bucket_name, object_name);
-    check_auth(&context)?;
-    implementation::get_object(context, bucket_name, object_name).await
+    let audit = check_auth(&context)?;
+    audit.finish(
+        time_operation(
+            "head_object",
+            implementation::head_object(context, bucket_name, object_name, headers),
+        )
+        .await,
+    )
 }
 
 pub async fn delete_object(
     mut context: Context,
     bucket_name: String,
     object_name: Tail,
-) -> Result<DeleteObjectResult, Rejection> {
+) -> Result<Response, Rejection> {
     let object_name = object_name.as_str().to_string();
     context.path = format!("{}/{}", bucket_name, object_name);
-    check_auth(&context)?;
-    implementation::delete_object(context, bucket_name, object_name).await
+    let audit = check_auth(&context)?;
+    audit.finish(
+        time_operation(
+            "delete_object",
+            implementation::delete_object(context, bucket_name, object_name),
+        )
+        .await,
+    )
+}
+
+/// removes many objects of a bucket at once, every object is authorised
+/// as if it was deleted with its own `DELETE /<bucket>/<object>`
+pub async fn delete_objects(
+    mut context: Context,
+    bucket_name: String,
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+) -> Result<Response, Rejection> {
+    context.path = bucket_name.to_string();
+    let audit = check_batch(&context)?;
+
+    if let Err(error) = check_json_content_type(content_type.as_deref(), &body) {
+        return audit.finish(Ok(
+            error.with_fields(serde_json::json!({"bucket": bucket_name}))
+        ));
+    }
+
+    let request = match serde_json::from_slice::<DeleteObjectsRequest>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(|request| request.validate().map(|()| request))
+    {
+        Ok(request) => request,
+        Err(e) => {
+            return audit.finish(Ok(ApiError::InvalidRequest(format!(
+                "invalid delete request, {}",
+                e
+            ))
+            .with_fields(serde_json::json!({"bucket": bucket_name}))))
+        }
+    };
+
+    let allowed = |object_name: &str| {
+        context.validate_path(
+            &warp::http::Method::DELETE,
+            &format!("{}/{}", bucket_name, object_name),
+        )
+    };
+    audit.finish(
+        time_operation(
+            "delete_objects",
+            implementation::delete_objects(&context, bucket_name.clone(), request, allowed),
+        )
+        .await,
+    )
+}
+
+pub async fn get_usage(mut context: Context, bucket_name: String) -> Result<Response, Rejection> {
+    context.path = bucket_name.to_string();
+    let audit = check_auth(&context)?;
+    audit.finish(time_operation("get_usage", implementation::get_usage(context, bucket_name)).await)
+}
+
+/// webhooks are authorised for the path `<bucket>?webhooks` and their deliveries for
+/// `<bucket>?webhook_deliveries`, a token for the bucket itself does not allow them
+pub async fn create_webhook(
+    mut context: Context,
+    bucket_name: String,
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+) -> Result<Response, Rejection> {
+    context.path = format!("{}?webhooks", bucket_name);
+    let audit = check_logged_in(&context)?;
+
+    if let Err(error) = check_json_content_type(content_type.as_deref(), &body) {
+        return audit.finish(Ok(
+            error.with_fields(serde_json::json!({"bucket": bucket_name}))
+        ));
+    }
+
+    let request = match serde_json::from_slice::<WebhookRequest>(&body)
+        .map_err(|e| e.to_string())
+        .and_then(|request| request.validate().map(|()| request))
+    {
+        Ok(request) => request,
+        Err(e) => {
+            return audit.finish(Ok(CreateWebhookResult {
+                bucket: bucket_name,
+                id: None,
+                validation_error: Some(format!("invalid webhook, {}", e)),
+            }))
+        }
+    };
+
+    audit.finish(
+        time_operation(
+            "create_webhook",
+            implementation::create_webhook(context, bucket_name, request),
+        )
+        .await,
+    )
+}
+
+pub async fn get_webhooks(
+    mut context: Context,
+    bucket_name: String,
+) -> Result<Response, Rejection> {
+    context.path = format!("{}?webhooks", bucket_name);
+    let audit = check_logged_in(&context)?;
+    audit.finish(
+        time_operation(
+            "get_webhooks",
+            implementation::get_webhooks(context, bucket_name),
+        )
+        .await,
+    )
+}
+
+pub async fn delete_webhook(
+    mut context: Context,
+    bucket_name: String,
+    options: WebhookOptions,
+) -> Result<Response, Rejection> {
+    context.path = format!("{}?webhooks", bucket_name);
+    let audit = check_logged_in(&context)?;
+    audit.finish(
+        time_operation(
+            "delete_webhook",
+            implementation::delete_webhook(context, bucket_name, options.id),
+        )
+        .await,
+    )
+}
+
+pub async fn get_webhook_deliveries(
+    mut context: Context,
+    bucket_name: String,
+) -> Result<Response, Rejection> {
+    context.path = format!("{}?webhook_deliveries", bucket_name);
+    let audit = check_logged_in(&context)?;
+    audit.finish(
+        time_operation(
+            "get_webhook_deliveries",
+            implementation::get_webhook_deliveries(context, bucket_name),
+        )
+        .await,
+    )
+}
+
+/// checks the stored objects every `interval` until the process exits
+pub async fn run_scrubber(client: Client, interval: std::time::Duration) {
+    // a full scrub reads everything, so it doesn't run right at startup
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        match time_operation("scrub", implementation::scrub(&client)).await {
+            Ok(0) => log::info!("scrubber found no problems"),
+            Ok(problems) => log::warn!("scrubber found {} problems", problems),
+            Err(e) => log::error!("scrubber failed: {}", e),
+        }
+    }
+}
+
+/// deletes expired objects every `interval` until the process exits
+pub async fn run_sweeper(client: Client, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match time_operation("sweep", implementation::sweep(&client)).await {
+            Ok(0) => log::debug!("sweeper removed no objects"),
+            Ok(removed) => log::info!("sweeper removed {} objects", removed),
+            Err(e) => log::error!("sweeper failed: {}", e),
+        }
+    }
+}
+
+pub async fn get_scrub_reports(mut context: Context) -> Result<Response, Rejection> {
+    context.path = String::from("scrub");
+    let audit = check_admin(&context)?;
+    audit.finish(
+        time_operation(
+            "get_scrub_reports",
+            implementation::get_scrub_reports(context),
+        )
+        .await,
+    )
+}
+
+pub async fn get_audit_log(mut context: Context, query: AuditQuery) -> Result<Response, Rejection> {
+    context.path = String::from("audit");
+    let audit = check_admin(&context)?;
+    audit.finish(
+        time_operation(
+            "get_audit_log",
+            implementation::get_audit_log(context, query),
+        )
+        .await,
+    )
+}
+
+pub async fn get_metrics(mut context: Context) -> Result<Response, Rejection> {
+    context.path = String::from("metrics");
+    let audit = check_admin(&context)?;
+    audit.finish(Ok(metrics::render()))
+}
+
+pub async fn get_organisation_rate_limit(
+    client: Client,
+    organisation_id: String,
+) -> Result<Option<RateLimit>, String> {
+    time_operation(
+        "get_organisation_rate_limit",
+        implementation::get_organisation_rate_limit(client, organisation_id),
+    )
+    .await
+}
+
+/// the cors rules of a bucket, `None` if the bucket doesn't exist
+pub async fn get_bucket_cors(
+    client: Client,
+    bucket_name: String,
+) -> Result<Option<Vec<CorsRule>>, String> {
+    time_operation(
+        "get_bucket_cors",
+        implementation::get_bucket_cors(client, bucket_name),
+    )
+    .await
 }
 
 pub async fn get_keypair_with_access_key(
     client: Client,
     access_key: String,
 ) -> Result<KeyPair, String> {
-    implementation::get_keypair_with_access_key(client, access_key).await
+    time_operation(
+        "get_keypair_with_access_key",
+        implementation::get_keypair_with_access_key(client, access_key),
+    )
+    .await
 }
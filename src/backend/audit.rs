@@ -0,0 +1,248 @@
+use crate::backend::{events, implementation, Client};
+use crate::basic::rejection_status;
+use crate::config::{AuditSink, Config};
+use crate::metrics;
+use crate::Context;
+use crate::GeneralResult;
+
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use warp::http::StatusCode;
+use warp::hyper::body::{Body, Bytes, HttpBody};
+use warp::reply::Response;
+use warp::{Buf, Rejection, Reply};
+
+static SENDER: OnceLock<mpsc::Sender<AuditRecord>> = OnceLock::new();
+/// records waiting for the writer, further records are dropped
+const QUEUE_SIZE: usize = 10_000;
+
+/// who did what, written for every authenticated request that passed the auth check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// seconds since the epoch
+    pub timestamp: i64,
+    /// access key of the jwt or policy, or subject of the client certificate,
+    /// missing in records written before anonymous requests were left out
+    pub sub: Option<String>,
+    pub jti: Option<String>,
+    pub organisation: String,
+    pub method: String,
+    pub path: String,
+    /// http status of the response
+    pub outcome: u16,
+    /// bytes uploaded, or the bytes of the response body sent before it ended or the client left
+    pub bytes: Option<u64>,
+    pub client_ip: Option<String>,
+    /// missing in records written before request ids were introduced
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+/// filter of the admin audit endpoint, `from` and `to` are seconds since the epoch
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub organisation: Option<String>,
+    pub sub: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// an operation that passed the auth check, recorded once its outcome is known
+/// unless it is anonymous
+pub struct AuditEntry {
+    record: AuditRecord,
+    uploaded: Arc<AtomicU64>,
+}
+
+impl AuditEntry {
+    pub fn new(context: &Context) -> AuditEntry {
+        let auth = context.auth.as_ref();
+
+        AuditEntry {
+            record: AuditRecord {
+                timestamp: now(),
+                sub: auth.map(|auth| auth.sub().to_string()),
+                jti: auth.and_then(|auth| auth.jti()).map(String::from),
+                organisation: context.organisation_id().to_string(),
+                method: context.method.to_string(),
+                path: context.path.clone(),
+                outcome: 0,
+                bytes: None,
+                client_ip: context.remote_addr.map(|address| address.ip().to_string()),
+                request_id: Some(context.request_id.to_string()),
+            },
+            uploaded: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// counts the bytes of the upload body for the record
+    pub fn count_upload<S, B, E>(&self, stream: S) -> impl Stream<Item = Result<B, E>>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: Buf,
+    {
+        let uploaded = self.uploaded.clone();
+        stream.map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                uploaded.fetch_add(chunk.remaining() as u64, Ordering::Relaxed);
+            }
+            chunk
+        })
+    }
+
+    /// records the outcome of the operation and passes its result on
+    pub fn finish<T: Reply>(mut self, result: Result<T, Rejection>) -> Result<Response, Rejection> {
+        let result = result.map(Reply::into_response);
+        if self.record.sub.is_none() {
+            return result;
+        }
+        let uploaded = self.uploaded.load(Ordering::Relaxed);
+
+        match result {
+            Ok(response) => {
+                self.record.outcome = response.status().as_u16();
+                if uploaded > 0 {
+                    self.record.bytes = Some(uploaded);
+                } else if let Some(length) = HttpBody::size_hint(response.body()).exact() {
+                    self.record.bytes = Some(length);
+                } else {
+                    return Ok(record_sent(self.record, response));
+                }
+                record(self.record);
+                Ok(response)
+            }
+            Err(rejection) => {
+                self.record.outcome = rejection_status(&rejection)
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+                    .as_u16();
+                self.record.bytes = (uploaded > 0).then_some(uploaded);
+                record(self.record);
+                Err(rejection)
+            }
+        }
+    }
+}
+
+/// a record waiting for the end of a streamed response body
+struct SentBytes {
+    record: Option<AuditRecord>,
+    bytes: u64,
+}
+
+impl SentBytes {
+    fn count(&mut self, chunk: &Bytes) {
+        self.bytes += chunk.len() as u64;
+    }
+}
+
+impl Drop for SentBytes {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.record.take() {
+            entry.bytes = Some(self.bytes);
+            record(entry);
+        }
+    }
+}
+
+/// records the bytes of the body once it was sent or the client went away
+fn record_sent(entry: AuditRecord, response: Response) -> Response {
+    let (parts, body) = response.into_parts();
+    let mut sent = SentBytes {
+        record: Some(entry),
+        bytes: 0,
+    };
+    let body = body.map(move |chunk: Result<Bytes, warp::hyper::Error>| {
+        if let Ok(chunk) = &chunk {
+            sent.count(chunk);
+        }
+        chunk
+    });
+
+    Response::from_parts(parts, Body::wrap_stream(body))
+}
+
+/// hands the record to the writer, it is dropped if the audit log is disabled
+/// or the writer can't keep up
+fn record(record: AuditRecord) {
+    if let Some(sender) = SENDER.get() {
+        match sender.try_send(record) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => metrics::audit_record_dropped(),
+            Err(TrySendError::Closed(_)) => {
+                log::error!("audit writer stopped, audit record dropped")
+            }
+        }
+    }
+}
+
+/// starts the writer of the configured sink
+pub fn setup(client: Client) -> GeneralResult<()> {
+    let config = Config::global();
+    if config.audit_sink == AuditSink::None {
+        return Ok(());
+    }
+
+    let file = if config.audit_sink == AuditSink::File {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.audit_file)
+            .map_err(|e| {
+                format!(
+                    "can not open audit log {}: {}",
+                    config.audit_file.display(),
+                    e
+                )
+            })?;
+        Some(tokio::fs::File::from_std(file))
+    } else {
+        None
+    };
+
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    if SENDER.set(sender).is_ok() {
+        tokio::spawn(write(client, config.audit_sink, file, receiver));
+    }
+    Ok(())
+}
+
+/// writes the records one after another so the sink keeps their order
+async fn write(
+    client: Client,
+    sink: AuditSink,
+    mut file: Option<tokio::fs::File>,
+    mut receiver: mpsc::Receiver<AuditRecord>,
+) {
+    while let Some(record) = receiver.recv().await {
+        let result = match (&sink, file.as_mut()) {
+            (AuditSink::Mongodb, _) => implementation::record_audit(&client, &record)
+                .await
+                .map_err(|e| e.to_string()),
+            (AuditSink::File, Some(file)) => write_line(file, &record).await,
+            (AuditSink::Nats, _) => events::publish_audit(&record).await,
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            log::error!("failed to write audit record: {}", e);
+        }
+    }
+}
+
+async fn write_line(file: &mut tokio::fs::File, record: &AuditRecord) -> Result<(), String> {
+    let mut line = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+    line.push(b'\n');
+    file.write_all(&line).await.map_err(|e| e.to_string())?;
+    file.flush().await.map_err(|e| e.to_string())
+}
@@ -0,0 +1,138 @@
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use warp::hyper::body::Bytes;
+
+/// checksums of the stored object, digests are hex encoded
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Checksums {
+    pub sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crc32c: Option<String>,
+}
+
+/// checksums the client sent along with the upload
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedChecksums {
+    /// base64 encoded, from the `Content-MD5` header
+    pub md5: Option<String>,
+    /// hex or base64 encoded, from the `x-checksum-sha256` header
+    pub sha256: Option<String>,
+    /// hex or base64 encoded, from the `x-checksum-crc32c` header
+    pub crc32c: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub algorithm: &'static str,
+}
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} checksum does not match the uploaded data",
+            self.algorithm
+        )
+    }
+}
+
+fn digest_matches(expected: &str, digest: &[u8]) -> bool {
+    let expected = expected.trim();
+
+    expected.eq_ignore_ascii_case(&hex::encode(digest))
+        || base64::decode(expected)
+            .map(|decoded| decoded == digest)
+            .unwrap_or(false)
+}
+
+impl ExpectedChecksums {
+    pub fn verify(&self, checksums: &Checksums) -> Result<(), ChecksumMismatch> {
+        let pairs = [
+            ("sha256", &self.sha256, Some(&checksums.sha256)),
+            ("md5", &self.md5, checksums.md5.as_ref()),
+            ("crc32c", &self.crc32c, checksums.crc32c.as_ref()),
+        ];
+
+        for (algorithm, expected, computed) in pairs {
+            if let (Some(expected), Some(computed)) = (expected, computed) {
+                let computed = hex::decode(computed).unwrap_or_default();
+                if !digest_matches(expected, &computed) {
+                    return Err(ChecksumMismatch { algorithm });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// computes the checksums of the data passing through `Hasher::stream`
+#[derive(Clone)]
+pub struct Hasher {
+    state: Arc<Mutex<HasherState>>,
+}
+
+struct HasherState {
+    sha256: Sha256,
+    md5: Option<Md5>,
+    crc32c: Option<u32>,
+    length: u64,
+}
+
+impl Hasher {
+    pub fn new(md5: bool, crc32c: bool) -> Hasher {
+        Hasher {
+            state: Arc::new(Mutex::new(HasherState {
+                sha256: Sha256::new(),
+                md5: md5.then(Md5::new),
+                crc32c: crc32c.then_some(0),
+                length: 0,
+            })),
+        }
+    }
+
+    /// feeds every chunk of the stream to the hasher
+    pub fn stream(
+        &self,
+        stream: impl futures::Stream<Item = Result<Bytes, std::io::Error>>,
+    ) -> impl futures::Stream<Item = Result<Bytes, std::io::Error>> {
+        use futures::stream::TryStreamExt;
+
+        let hasher = self.clone();
+        stream.inspect_ok(move |chunk| hasher.update(chunk))
+    }
+
+    pub fn update(&self, data: &[u8]) {
+        let mut state = self.state.lock().expect("hasher lock poisoned");
+        state.sha256.update(data);
+        if let Some(md5) = &mut state.md5 {
+            md5.update(data);
+        }
+        if let Some(crc32c) = &mut state.crc32c {
+            *crc32c = crc32c::crc32c_append(*crc32c, data);
+        }
+        state.length += data.len() as u64;
+    }
+
+    /// amount of bytes hashed so far
+    pub fn length(&self) -> u64 {
+        self.state.lock().expect("hasher lock poisoned").length
+    }
+
+    pub fn finalize(&self) -> Checksums {
+        let state = self.state.lock().expect("hasher lock poisoned");
+
+        Checksums {
+            sha256: hex::encode(state.sha256.clone().finalize()),
+            md5: state
+                .md5
+                .as_ref()
+                .map(|md5| hex::encode(md5.clone().finalize())),
+            crc32c: state.crc32c.map(|crc32c| hex::encode(crc32c.to_be_bytes())),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder, ZstdDecoder, ZstdEncoder};
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncBufRead;
+use tokio_util::either::Either;
+use tokio_util::io::{ReaderStream, StreamReader};
+use warp::hyper::body::Bytes;
+
+/// codec used to compress objects while they are stored
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    /// name as used in the metadata and the `Content-Encoding` header
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "zstd" => Some(Compression::Zstd),
+            "gzip" => Some(Compression::Gzip),
+            _ => None,
+        }
+    }
+
+    /// checks if the client can receive the compressed bytes directly
+    pub fn accepted_by(&self, accept_encoding: Option<&str>) -> bool {
+        accept_encoding
+            .unwrap_or_default()
+            .split(',')
+            .any(|encoding| {
+                let mut parts = encoding.split(';').map(str::trim);
+                let name = parts.next().unwrap_or_default();
+                let rejected = parts.any(|parameter| {
+                    parameter
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        == Some(0.0)
+                });
+
+                name.eq_ignore_ascii_case(self.name()) && !rejected
+            })
+    }
+}
+
+/// compresses the data read from `reader` if a codec is given
+pub fn encode<R: AsyncBufRead>(
+    compression: Option<Compression>,
+    reader: R,
+) -> Either<R, Either<ZstdEncoder<R>, GzipEncoder<R>>> {
+    match compression {
+        None => Either::Left(reader),
+        Some(Compression::Zstd) => Either::Right(Either::Left(ZstdEncoder::new(reader))),
+        Some(Compression::Gzip) => Either::Right(Either::Right(GzipEncoder::new(reader))),
+    }
+}
+
+/// decompresses a stream of stored chunks
+pub fn decode_stream<S>(
+    compression: Compression,
+    stream: S,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>>,
+{
+    let reader = StreamReader::new(stream);
+    let decoder = match compression {
+        Compression::Zstd => Either::Left(ZstdDecoder::new(reader)),
+        Compression::Gzip => Either::Right(GzipDecoder::new(reader)),
+    };
+
+    ReaderStream::new(decoder)
+}
@@ -0,0 +1,99 @@
+use futures::stream::{Stream, StreamExt};
+use warp::hyper::body::Bytes;
+
+/// amount of bytes read from the start of the upload to detect the content type
+pub const SNIFF_SIZE: usize = 8192;
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// the content type the client declared and the ones detected from the upload
+#[derive(Debug)]
+pub struct ContentTypes {
+    pub declared: Option<String>,
+    /// detected from the magic bytes
+    pub sniffed: Option<&'static str>,
+    /// guessed from the filename extension
+    pub guessed: Option<&'static str>,
+}
+
+impl ContentTypes {
+    pub fn detect(declared: Option<String>, data: &[u8], filename: &str) -> ContentTypes {
+        ContentTypes {
+            declared,
+            sniffed: sniff(data),
+            guessed: from_extension(filename),
+        }
+    }
+
+    pub fn detected(&self) -> Option<&'static str> {
+        self.sniffed.or(self.guessed)
+    }
+
+    /// the declared content type, unless the client left it out, only sent the generic one
+    /// or the magic bytes contradict it, the declared one is kept as `declaredContentType`
+    pub fn content_type(&self) -> String {
+        match (&self.declared, self.sniffed) {
+            (Some(declared), Some(sniffed)) if essence(declared) != sniffed => sniffed.to_string(),
+            (Some(declared), _) if essence(declared) != DEFAULT_CONTENT_TYPE => {
+                declared.to_string()
+            }
+            _ => self.detected().unwrap_or(DEFAULT_CONTENT_TYPE).to_string(),
+        }
+    }
+}
+
+/// the content type without parameters, so `text/plain; charset=utf-8` becomes `text/plain`
+pub fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// matches a content type against a pattern like `image/png`, `image/*` or `*/*`
+pub fn matches_pattern(content_type: &str, pattern: &str) -> bool {
+    let content_type = essence(content_type);
+    let pattern = essence(pattern);
+
+    match pattern.split_once('/') {
+        Some(("*", "*")) => true,
+        Some((kind, "*")) => content_type.split('/').next() == Some(kind),
+        _ => content_type == pattern,
+    }
+}
+
+/// detects the content type from the magic bytes at the start of the data
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    infer::get(data).map(|kind| kind.mime_type())
+}
+
+/// guesses the content type from the extension of the filename
+pub fn from_extension(filename: &str) -> Option<&'static str> {
+    mime_guess::from_path(filename).first_raw()
+}
+
+/// reads the first `size` bytes of the stream,
+/// returns these bytes and a stream that still yields the complete data
+pub async fn peek<S>(
+    mut stream: S,
+    size: usize,
+) -> Result<(Bytes, impl Stream<Item = Result<Bytes, std::io::Error>>), std::io::Error>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Unpin,
+{
+    let mut prefix = Vec::new();
+    while prefix.len() < size {
+        match stream.next().await {
+            Some(chunk) => prefix.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+
+    let prefix = Bytes::from(prefix);
+    let head = prefix.clone();
+    Ok((
+        prefix,
+        futures::stream::once(async { Ok(head) }).chain(stream),
+    ))
+}
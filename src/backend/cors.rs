@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+/// methods of the basic api, allowed when a rule lists none
+const METHODS: [&str; 4] = ["GET", "HEAD", "POST", "DELETE"];
+
+/// which cross-origin requests browsers may make to a bucket, like the cors rules of s3
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsRule {
+    /// origins like `https://app.example.com`, a single `*` matches any part of an origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// request headers scripts may send, `*` allows all of them
+    pub allowed_headers: Vec<String>,
+    /// response headers scripts may read, like `etag`
+    pub exposed_headers: Vec<String>,
+    /// seconds browsers may cache the answer to a preflight request
+    pub max_age: Option<u64>,
+}
+
+impl CorsRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allowed_origins.is_empty() {
+            return Err(String::from("allowed_origins must not be empty"));
+        }
+        if let Some(origin) = self
+            .allowed_origins
+            .iter()
+            .find(|origin| origin.matches('*').count() > 1)
+        {
+            return Err(format!("origin {} has more than one *", origin));
+        }
+        if let Some(method) = self.allowed_methods.iter().find(|method| {
+            !METHODS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(method))
+        }) {
+            return Err(format!("unknown method {}", method));
+        }
+
+        Ok(())
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| match allowed.split_once('*') {
+                Some((prefix, suffix)) => {
+                    origin.len() >= prefix.len() + suffix.len()
+                        && starts_with_ignore_case(origin, prefix)
+                        && ends_with_ignore_case(origin, suffix)
+                }
+                None => allowed.eq_ignore_ascii_case(origin),
+            })
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.methods()
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    /// `headers` is the comma separated list of `access-control-request-headers`
+    pub fn allows_headers(&self, headers: &str) -> bool {
+        headers
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .all(|header| {
+                self.allowed_headers
+                    .iter()
+                    .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(header))
+            })
+    }
+
+    /// the allowed methods, all of the api if none are listed
+    pub fn methods(&self) -> Vec<&str> {
+        if self.allowed_methods.is_empty() {
+            METHODS.to_vec()
+        } else {
+            self.allowed_methods.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+fn starts_with_ignore_case(value: &str, prefix: &str) -> bool {
+    value
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+fn ends_with_ignore_case(value: &str, suffix: &str) -> bool {
+    value
+        .len()
+        .checked_sub(suffix.len())
+        .and_then(|start| value.get(start..))
+        .is_some_and(|end| end.eq_ignore_ascii_case(suffix))
+}
+
+/// the first rule allowing `method` from `origin`
+pub fn find_rule<'a>(rules: &'a [CorsRule], origin: &str, method: &str) -> Option<&'a CorsRule> {
+    rules
+        .iter()
+        .find(|rule| rule.allows_origin(origin) && rule.allows_method(method))
+}
@@ -0,0 +1,338 @@
+use crate::config::Config;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use warp::hyper::body::Bytes;
+
+/// plaintext bytes per encrypted segment, every segment can be decrypted on its own
+/// so the position of any byte in the stored data is known
+pub const SEGMENT_SIZE: usize = 64 * 1024;
+pub const ALGORITHM: &str = "AES256-GCM";
+const TAG_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+/// which key wraps the data key of the object
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    /// the master key from the config
+    Server,
+    /// the key the client sends with every request, it is never stored
+    Customer,
+}
+
+/// stored in the metadata of encrypted objects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionInfo {
+    pub algorithm: String,
+    pub key_source: KeySource,
+    /// base64 of the nonce and the data key encrypted with the master or customer key
+    pub wrapped_key: String,
+    pub segment_size: u64,
+}
+
+#[derive(Debug)]
+pub enum EncryptionError {
+    MissingMasterKey,
+    MissingCustomerKey,
+    InvalidKey(String),
+    WrongKey,
+}
+
+impl std::fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncryptionError::MissingMasterKey => write!(f, "no master key configured"),
+            EncryptionError::MissingCustomerKey => {
+                write!(f, "object is encrypted with a customer key")
+            }
+            EncryptionError::InvalidKey(reason) => write!(f, "invalid encryption key, {}", reason),
+            EncryptionError::WrongKey => write!(f, "encryption key does not match"),
+        }
+    }
+}
+
+impl std::error::Error for EncryptionError {}
+
+/// key encrypting the data keys, either the master key or a customer key
+#[derive(zeroize::Zeroize, zeroize::ZeroizeOnDrop)]
+pub struct EncryptionKey {
+    key: [u8; 32],
+}
+
+impl EncryptionKey {
+    /// parses a base64 encoded 256 bit key
+    pub fn from_base64(encoded: &str) -> Result<EncryptionKey, EncryptionError> {
+        let decoded = base64::decode(encoded.trim())
+            .map_err(|e| EncryptionError::InvalidKey(e.to_string()))?;
+        let key = <[u8; 32]>::try_from(decoded.as_slice()).map_err(|_| {
+            EncryptionError::InvalidKey(format!("expected 32 bytes, got {}", decoded.len()))
+        })?;
+
+        Ok(EncryptionKey { key })
+    }
+
+    pub fn master() -> Result<EncryptionKey, EncryptionError> {
+        match &Config::global().master_key {
+            Some(master_key) => EncryptionKey::from_base64(master_key),
+            None => Err(EncryptionError::MissingMasterKey),
+        }
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.key))
+    }
+
+    /// encrypts a small value for storage, base64 of the nonce followed by the ciphertext
+    pub fn seal(&self, data: &[u8]) -> Result<String, EncryptionError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher()
+                .encrypt(&nonce, data)
+                .map_err(|_| EncryptionError::WrongKey)?,
+        );
+
+        Ok(base64::encode(sealed))
+    }
+
+    /// decrypts a value sealed with `seal`
+    pub fn open(&self, sealed: &str) -> Result<Vec<u8>, EncryptionError> {
+        let sealed = base64::decode(sealed).map_err(|_| EncryptionError::WrongKey)?;
+        if sealed.len() < NONCE_SIZE {
+            return Err(EncryptionError::WrongKey);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| EncryptionError::WrongKey)
+    }
+}
+
+/// random key the content of a single object is encrypted with
+#[derive(Clone)]
+pub struct DataKey {
+    cipher: Aes256Gcm,
+    segment_size: usize,
+}
+
+impl DataKey {
+    fn decrypt(&self, index: u64, data: &[u8], last: bool) -> Result<Bytes, std::io::Error> {
+        self.cipher
+            .decrypt(Nonce::from_slice(&segment_nonce(index, last)), data)
+            .map(Bytes::from)
+            .map_err(|_| std::io::Error::other(format!("segment {} failed authentication", index)))
+    }
+
+    /// creates a new data key, wrapped with `key` for storage
+    pub fn generate(
+        key: &EncryptionKey,
+        key_source: KeySource,
+    ) -> Result<(DataKey, EncryptionInfo), EncryptionError> {
+        let data_key = Aes256Gcm::generate_key(&mut OsRng);
+        let wrapped_key = key.seal(data_key.as_slice())?;
+
+        Ok((
+            DataKey {
+                cipher: Aes256Gcm::new(&data_key),
+                segment_size: SEGMENT_SIZE,
+            },
+            EncryptionInfo {
+                algorithm: ALGORITHM.to_string(),
+                key_source,
+                wrapped_key,
+                segment_size: SEGMENT_SIZE as u64,
+            },
+        ))
+    }
+
+    /// recovers the data key of an object
+    pub fn unwrap(info: &EncryptionInfo, key: &EncryptionKey) -> Result<DataKey, EncryptionError> {
+        let data_key = key.open(&info.wrapped_key)?;
+        let cipher = Aes256Gcm::new_from_slice(&data_key).map_err(|_| EncryptionError::WrongKey)?;
+
+        Ok(DataKey {
+            cipher,
+            segment_size: info.segment_size as usize,
+        })
+    }
+}
+
+/// data key for a new object, a customer key takes precedence over the bucket setting
+pub fn new_object_key(
+    customer_key: Option<&str>,
+    encrypt: bool,
+) -> Result<Option<(DataKey, EncryptionInfo)>, EncryptionError> {
+    match customer_key {
+        Some(customer_key) => DataKey::generate(
+            &EncryptionKey::from_base64(customer_key)?,
+            KeySource::Customer,
+        )
+        .map(Some),
+        None if encrypt => {
+            DataKey::generate(&EncryptionKey::master()?, KeySource::Server).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// data key of a stored object
+pub fn object_key(
+    info: &EncryptionInfo,
+    customer_key: Option<&str>,
+) -> Result<DataKey, EncryptionError> {
+    let key = match info.key_source {
+        KeySource::Server => EncryptionKey::master()?,
+        KeySource::Customer => {
+            EncryptionKey::from_base64(customer_key.ok_or(EncryptionError::MissingCustomerKey)?)?
+        }
+    };
+
+    DataKey::unwrap(info, &key)
+}
+
+/// nonce of a segment, the flag for the last segment detects truncated data
+fn segment_nonce(index: u64, last: bool) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[..8].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_SIZE - 1] = last as u8;
+    nonce
+}
+
+/// splits the stream into segments of `size` bytes and transforms each of them,
+/// the last segment has at most `size` bytes and is passed even if it is empty,
+/// the segments are counted from `first`
+fn segments<S, F>(
+    stream: S,
+    size: usize,
+    first: u64,
+    transform: F,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>>,
+    F: Fn(u64, &[u8], bool) -> Result<Bytes, std::io::Error>,
+{
+    let state = (Box::pin(stream), Vec::new(), first, false, transform);
+
+    futures::stream::try_unfold(
+        state,
+        move |(mut stream, mut buffer, index, done, transform)| async move {
+            if done {
+                return Ok(None);
+            }
+
+            // one more byte than the segment is needed to know it is not the last one
+            while buffer.len() <= size {
+                match stream.next().await {
+                    Some(chunk) => buffer.extend_from_slice(&chunk?),
+                    None => {
+                        let segment = transform(index, &buffer, true)?;
+                        return Ok(Some((
+                            segment,
+                            (stream, Vec::new(), index + 1, true, transform),
+                        )));
+                    }
+                }
+            }
+
+            let rest = buffer.split_off(size);
+            let segment = transform(index, &buffer, false)?;
+            Ok(Some((segment, (stream, rest, index + 1, false, transform))))
+        },
+    )
+}
+
+pub fn encrypt_stream<S>(
+    key: DataKey,
+    stream: S,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>>,
+{
+    let segment_size = key.segment_size;
+    segments(stream, segment_size, 0, move |index, data, last| {
+        key.cipher
+            .encrypt(Nonce::from_slice(&segment_nonce(index, last)), data)
+            .map(Bytes::from)
+            .map_err(|_| std::io::Error::other(format!("can not encrypt segment {}", index)))
+    })
+}
+
+pub fn decrypt_stream<S>(
+    key: DataKey,
+    stream: S,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>>,
+{
+    let segment_size = key.segment_size;
+    segments(
+        stream,
+        segment_size + TAG_SIZE,
+        0,
+        move |index, data, last| key.decrypt(index, data, last),
+    )
+}
+
+/// the stored segments holding a range of the plaintext
+#[derive(Debug)]
+pub struct StoredSegments {
+    /// the segments are stored in the bytes `stored_start..stored_end`
+    pub stored_start: u64,
+    pub stored_end: u64,
+    pub first: u64,
+    /// the last segment of the object is encrypted with another nonce
+    pub last_of_object: u64,
+    /// plaintext bytes of the first segment in front of the range
+    pub skip: u64,
+}
+
+/// the segments the plaintext bytes `start..=end` of an object are stored in
+pub fn stored_segments(
+    stored_length: u64,
+    segment_size: u64,
+    start: u64,
+    end: u64,
+) -> StoredSegments {
+    let stored_segment_size = segment_size + TAG_SIZE as u64;
+    let first = start / segment_size;
+    let last = end / segment_size;
+
+    StoredSegments {
+        stored_start: first * stored_segment_size,
+        stored_end: ((last + 1) * stored_segment_size).min(stored_length),
+        first,
+        last_of_object: stored_length
+            .div_ceil(stored_segment_size)
+            .saturating_sub(1),
+        skip: start - first * segment_size,
+    }
+}
+
+/// decrypts only the segments of `range`, `stream` has to hold their stored bytes
+pub fn decrypt_segments<S>(
+    key: DataKey,
+    range: &StoredSegments,
+    stream: S,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>>,
+{
+    let segment_size = key.segment_size;
+    let last_of_object = range.last_of_object;
+    segments(
+        stream,
+        segment_size + TAG_SIZE,
+        range.first,
+        move |index, data, _| key.decrypt(index, data, index == last_of_object),
+    )
+}
+
+/// size of the data before it was encrypted into `stored_length` bytes
+pub fn plaintext_length(stored_length: u64, segment_size: u64) -> u64 {
+    let segments = stored_length.div_ceil(segment_size + TAG_SIZE as u64);
+    stored_length.saturating_sub(segments * TAG_SIZE as u64)
+}
@@ -0,0 +1,260 @@
+use crate::backend::encryption::EncryptionError;
+use crate::backend::types::CreateObjectValidationError;
+use crate::request_id;
+
+use warp::http::header::{HeaderValue, CONTENT_RANGE, CONTENT_TYPE, RETRY_AFTER};
+use warp::http::StatusCode;
+use warp::reject::{Reject, Rejection};
+use warp::reply::Response;
+
+/// every error the api answers with, the body is
+/// `{"code": "<code>", "error": "<message>", "request_id": "<id>"}`
+/// plus fields like the bucket it is about
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    /// no route matches the request
+    NotFound,
+    MethodNotAllowed,
+    BucketNotFound,
+    ObjectNotFound,
+    WebhookNotFound,
+    BucketAlreadyExists,
+    ObjectAlreadyExists,
+    BucketNotEmpty,
+    InvalidBucket(String),
+    InvalidWebhook(String),
+    /// malformed query string, header or body
+    InvalidRequest(String),
+    MissingContentType,
+    UnsupportedMediaType(String),
+    PayloadTooLarge(String),
+    QuotaExceeded(String),
+    ChecksumMismatch(String),
+    InvalidExpiry(String),
+    MissingEncryptionKey,
+    InvalidEncryptionKey(String),
+    WrongEncryptionKey,
+    Unauthorised(String),
+    /// the range starts past the end of the object of this length
+    RangeNotSatisfiable(u64),
+    /// a file of a form upload does not satisfy its post policy
+    PolicyViolation(String),
+    /// a preflight request no cors rule of the bucket allows
+    CorsNotAllowed(String),
+    /// seconds until the request can be retried
+    TooManyRequests(u64),
+    /// the backend timed out or is not reachable, retrying later can succeed
+    BackendUnavailable(String),
+    /// the detail is logged, clients only get a generic message
+    Internal(String),
+}
+
+impl ApiError {
+    /// stable machine readable name of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound => "not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::BucketNotFound => "bucket_not_found",
+            ApiError::ObjectNotFound => "object_not_found",
+            ApiError::WebhookNotFound => "webhook_not_found",
+            ApiError::BucketAlreadyExists => "bucket_already_exists",
+            ApiError::ObjectAlreadyExists => "object_already_exists",
+            ApiError::BucketNotEmpty => "bucket_not_empty",
+            ApiError::InvalidBucket(_) => "invalid_bucket",
+            ApiError::InvalidWebhook(_) => "invalid_webhook",
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::MissingContentType => "missing_content_type",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::QuotaExceeded(_) => "quota_exceeded",
+            ApiError::ChecksumMismatch(_) => "checksum_mismatch",
+            ApiError::InvalidExpiry(_) => "invalid_expiry",
+            ApiError::MissingEncryptionKey => "missing_encryption_key",
+            ApiError::InvalidEncryptionKey(_) => "invalid_encryption_key",
+            ApiError::WrongEncryptionKey => "wrong_encryption_key",
+            ApiError::Unauthorised(_) => "unauthorised",
+            ApiError::RangeNotSatisfiable(_) => "range_not_satisfiable",
+            ApiError::PolicyViolation(_) => "policy_violation",
+            ApiError::CorsNotAllowed(_) => "cors_not_allowed",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::BackendUnavailable(_) => "backend_unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound
+            | ApiError::BucketNotFound
+            | ApiError::ObjectNotFound
+            | ApiError::WebhookNotFound => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::BucketAlreadyExists
+            | ApiError::ObjectAlreadyExists
+            | ApiError::BucketNotEmpty => StatusCode::CONFLICT,
+            ApiError::InvalidBucket(_)
+            | ApiError::InvalidWebhook(_)
+            | ApiError::InvalidRequest(_)
+            | ApiError::MissingContentType
+            | ApiError::ChecksumMismatch(_)
+            | ApiError::InvalidExpiry(_)
+            | ApiError::MissingEncryptionKey
+            | ApiError::InvalidEncryptionKey(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::WrongEncryptionKey
+            | ApiError::PolicyViolation(_)
+            | ApiError::CorsNotAllowed(_) => StatusCode::FORBIDDEN,
+            ApiError::Unauthorised(_) => StatusCode::UNAUTHORIZED,
+            ApiError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::BackendUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn reject(self) -> Rejection {
+        warp::reject::custom(self)
+    }
+
+    /// the error for rejections of warp's own filters
+    pub fn from_rejection(rejection: &Rejection) -> ApiError {
+        if let Some(error) = rejection.find::<ApiError>() {
+            error.clone()
+        } else if rejection.is_not_found() {
+            ApiError::NotFound
+        } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+            ApiError::MethodNotAllowed
+        } else if let Some(e) = rejection.find::<warp::reject::InvalidQuery>() {
+            ApiError::InvalidRequest(e.to_string())
+        } else if let Some(e) = rejection.find::<warp::reject::InvalidHeader>() {
+            ApiError::InvalidRequest(e.to_string())
+        } else if let Some(e) = rejection.find::<warp::reject::MissingHeader>() {
+            ApiError::InvalidRequest(e.to_string())
+        } else if let Some(e) = rejection.find::<warp::reject::LengthRequired>() {
+            ApiError::InvalidRequest(e.to_string())
+        } else if let Some(e) = rejection.find::<warp::reject::PayloadTooLarge>() {
+            ApiError::PayloadTooLarge(e.to_string())
+        } else if let Some(e) = rejection.find::<warp::reject::UnsupportedMediaType>() {
+            ApiError::UnsupportedMediaType(e.to_string())
+        } else {
+            ApiError::Internal(format!("unhandled rejection: {:?}", rejection))
+        }
+    }
+
+    /// the response with `fields`, a json object, added to the body
+    pub fn with_fields(self, fields: serde_json::Value) -> Response {
+        let mut body = match fields {
+            serde_json::Value::Object(fields) => fields,
+            _ => serde_json::Map::new(),
+        };
+        body.insert("code".into(), self.code().into());
+        body.insert("error".into(), self.to_string().into());
+        if let Some(request_id) = request_id::current() {
+            body.insert("request_id".into(), request_id.to_string().into());
+        }
+
+        let mut response = Response::new(serde_json::Value::Object(body).to_string().into());
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let ApiError::TooManyRequests(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        if let ApiError::RangeNotSatisfiable(length) = self {
+            if let Ok(content_range) = HeaderValue::from_str(&format!("bytes */{}", length)) {
+                response.headers_mut().insert(CONTENT_RANGE, content_range);
+            }
+        }
+        *response.status_mut() = self.status();
+
+        response
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::NotFound => write!(f, "not found"),
+            ApiError::MethodNotAllowed => write!(f, "method not allowed"),
+            ApiError::BucketNotFound => write!(f, "bucket not found"),
+            ApiError::ObjectNotFound => write!(f, "object not found"),
+            ApiError::WebhookNotFound => write!(f, "webhook not found"),
+            ApiError::BucketAlreadyExists => write!(f, "bucket already exists"),
+            ApiError::ObjectAlreadyExists => write!(f, "object already exists"),
+            ApiError::BucketNotEmpty => write!(f, "bucket is not empty"),
+            ApiError::MissingContentType => write!(f, "content-type header is missing"),
+            ApiError::MissingEncryptionKey => write!(f, "object is encrypted with a customer key"),
+            ApiError::WrongEncryptionKey => write!(f, "encryption key does not match"),
+            ApiError::RangeNotSatisfiable(length) => {
+                write!(f, "range is outside of the {} bytes of the object", length)
+            }
+            ApiError::TooManyRequests(retry_after) => {
+                write!(f, "rate limit exceeded, retry in {} seconds", retry_after)
+            }
+            ApiError::BackendUnavailable(_) => write!(f, "backend unavailable"),
+            ApiError::Internal(_) => write!(f, "internal server error"),
+            ApiError::InvalidBucket(reason)
+            | ApiError::InvalidWebhook(reason)
+            | ApiError::InvalidRequest(reason)
+            | ApiError::UnsupportedMediaType(reason)
+            | ApiError::PayloadTooLarge(reason)
+            | ApiError::QuotaExceeded(reason)
+            | ApiError::ChecksumMismatch(reason)
+            | ApiError::InvalidExpiry(reason)
+            | ApiError::InvalidEncryptionKey(reason)
+            | ApiError::Unauthorised(reason)
+            | ApiError::PolicyViolation(reason)
+            | ApiError::CorsNotAllowed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Reject for ApiError {}
+
+impl warp::Reply for ApiError {
+    fn into_response(self) -> Response {
+        self.with_fields(serde_json::json!({}))
+    }
+}
+
+impl From<CreateObjectValidationError> for ApiError {
+    fn from(error: CreateObjectValidationError) -> ApiError {
+        match error {
+            CreateObjectValidationError::BucketNotFound => ApiError::BucketNotFound,
+            CreateObjectValidationError::QuotaExceeded(reason) => ApiError::QuotaExceeded(reason),
+            CreateObjectValidationError::PayloadTooLarge(reason) => {
+                ApiError::PayloadTooLarge(reason)
+            }
+            CreateObjectValidationError::UnsupportedMediaType(reason) => {
+                ApiError::UnsupportedMediaType(reason)
+            }
+            CreateObjectValidationError::ChecksumMismatch(reason) => {
+                ApiError::ChecksumMismatch(reason)
+            }
+            CreateObjectValidationError::InvalidEncryptionKey(reason) => {
+                ApiError::InvalidEncryptionKey(reason)
+            }
+            CreateObjectValidationError::InvalidExpiry(reason) => ApiError::InvalidExpiry(reason),
+            CreateObjectValidationError::PolicyViolation(reason) => {
+                ApiError::PolicyViolation(reason)
+            }
+            CreateObjectValidationError::Unauthorised(reason) => ApiError::Unauthorised(reason),
+        }
+    }
+}
+
+impl From<EncryptionError> for ApiError {
+    fn from(error: EncryptionError) -> ApiError {
+        match error {
+            EncryptionError::MissingMasterKey => ApiError::Internal(error.to_string()),
+            EncryptionError::MissingCustomerKey => ApiError::MissingEncryptionKey,
+            EncryptionError::InvalidKey(_) => ApiError::InvalidEncryptionKey(error.to_string()),
+            EncryptionError::WrongKey => ApiError::WrongEncryptionKey,
+        }
+    }
+}
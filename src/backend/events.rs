@@ -0,0 +1,191 @@
+use crate::backend::audit::AuditRecord;
+use crate::backend::webhooks;
+use crate::GeneralResult;
+use serde::Serialize;
+
+#[cfg(feature = "nats-events")]
+static CONNECTION: tokio::sync::OnceCell<nats::asynk::Connection> =
+    tokio::sync::OnceCell::const_new();
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum EventKind {
+    #[serde(rename = "object.created")]
+    ObjectCreated,
+    #[serde(rename = "object.deleted")]
+    ObjectDeleted,
+    #[serde(rename = "bucket.created")]
+    BucketCreated,
+    #[serde(rename = "bucket.deleted")]
+    BucketDeleted,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::ObjectCreated => "object.created",
+            EventKind::ObjectDeleted => "object.deleted",
+            EventKind::BucketCreated => "bucket.created",
+            EventKind::BucketDeleted => "bucket.deleted",
+        }
+    }
+}
+
+/// change of a bucket or object, published as json
+#[derive(Debug, Serialize)]
+pub struct Event {
+    pub event: EventKind,
+    pub organisation: String,
+    pub bucket: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// seconds since the epoch
+    pub timestamp: i64,
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl Event {
+    pub fn bucket(event: EventKind, organisation: &str, bucket: &str) -> Event {
+        Event {
+            event,
+            organisation: organisation.to_string(),
+            bucket: bucket.to_string(),
+            filename: None,
+            size: None,
+            content_type: None,
+            timestamp: now(),
+        }
+    }
+
+    pub fn object(
+        event: EventKind,
+        organisation: &str,
+        bucket: &str,
+        filename: &str,
+        size: u64,
+        content_type: Option<String>,
+    ) -> Event {
+        Event {
+            filename: Some(filename.to_string()),
+            size: Some(size),
+            content_type,
+            ..Event::bucket(event, organisation, bucket)
+        }
+    }
+}
+
+/// connects to the nats server from the config in the background,
+/// an unreachable server doesn't keep the service from starting
+#[cfg(feature = "nats-events")]
+pub async fn setup() -> GeneralResult<()> {
+    tokio::spawn(connect());
+    Ok(())
+}
+
+/// longest wait between two connection attempts
+#[cfg(feature = "nats-events")]
+const MAX_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// retries until the server is reachable, events are not published until then,
+/// the client reconnects on its own once connected
+#[cfg(feature = "nats-events")]
+async fn connect() {
+    let url = crate::config::Config::global().nats_url.as_str();
+    let mut delay = std::time::Duration::from_secs(1);
+    loop {
+        match nats::asynk::connect(url).await {
+            Ok(connection) => {
+                CONNECTION.set(connection).ok();
+                log::info!("publishing events to {}", url);
+                return;
+            }
+            Err(e) => {
+                log::warn!(
+                    "can not connect to nats at {}, retrying in {:?}: {}",
+                    url,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "nats-events"))]
+pub async fn setup() -> GeneralResult<()> {
+    Ok(())
+}
+
+/// hands the event to the webhooks of the bucket and publishes it over nats in the background,
+/// failures are only logged as the operation itself already succeeded
+pub fn publish(event: Event) {
+    webhooks::dispatch(&event);
+    publish_nats(event);
+}
+
+/// publishes the event on `<prefix>.<event>`
+#[cfg(feature = "nats-events")]
+fn publish_nats(event: Event) {
+    let connection = match CONNECTION.get() {
+        Some(connection) => connection,
+        None => {
+            log::debug!("not connected to nats, dropping {}", event.event.name());
+            return;
+        }
+    };
+
+    let subject = format!(
+        "{}.{}",
+        crate::config::Config::global().nats_subject_prefix,
+        event.event.name()
+    );
+    let payload = match serde_json::to_vec(&event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::error!("failed to serialize {} event: {}", event.event.name(), e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        if let Err(e) = connection.publish(&subject, payload).await {
+            log::warn!("failed to publish {}: {}", subject, e);
+        }
+    });
+}
+
+#[cfg(not(feature = "nats-events"))]
+fn publish_nats(_event: Event) {}
+
+/// publishes the record on `<prefix>.audit`
+#[cfg(feature = "nats-events")]
+pub async fn publish_audit(record: &AuditRecord) -> Result<(), String> {
+    let connection = CONNECTION
+        .get()
+        .ok_or_else(|| String::from("not connected to nats"))?;
+    let subject = format!(
+        "{}.audit",
+        crate::config::Config::global().nats_subject_prefix
+    );
+    let payload = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+
+    connection
+        .publish(&subject, payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "nats-events"))]
+pub async fn publish_audit(_record: &AuditRecord) -> Result<(), String> {
+    Err(String::from("built without the nats-events feature"))
+}
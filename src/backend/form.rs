@@ -0,0 +1,97 @@
+use crate::backend::error::ApiError;
+
+use futures::TryStreamExt;
+use multer::{Field, Multipart};
+use std::collections::HashMap;
+use warp::hyper::body::Bytes;
+use warp::Rejection;
+
+/// longest text field, the policy document included
+const MAX_FIELD_LENGTH: usize = 64 * 1024;
+
+/// text fields that are not passed on to the policy conditions
+const CREDENTIAL_FIELDS: [&str; 3] = ["access_key", "policy", "signature"];
+
+fn invalid_form(error: impl std::fmt::Display) -> Rejection {
+    ApiError::InvalidRequest(format!("invalid multipart body, {}", error)).reject()
+}
+
+/// parses a `multipart/form-data` body while it is read
+pub fn parse(
+    content_type: &str,
+    body: impl futures::Stream<Item = Result<Bytes, warp::Error>> + Send + 'static,
+) -> Result<Multipart<'static>, Rejection> {
+    let boundary = multer::parse_boundary(content_type).map_err(invalid_form)?;
+    Ok(Multipart::new(body, boundary))
+}
+
+pub async fn next_field(
+    form: &mut Multipart<'static>,
+) -> Result<Option<Field<'static>>, Rejection> {
+    form.next_field().await.map_err(invalid_form)
+}
+
+/// whether the part is a file, browsers send an empty filename when no file was chosen
+pub fn is_file(field: &Field) -> bool {
+    field.file_name().is_some()
+}
+
+/// the text fields in front of the first file, as with s3 they have to come before the files
+/// so the policy can be checked before anything is stored
+pub async fn read_fields(
+    form: &mut Multipart<'static>,
+) -> Result<(HashMap<String, String>, Option<Field<'static>>), Rejection> {
+    let mut fields = HashMap::new();
+    while let Some(mut field) = next_field(form).await? {
+        if is_file(&field) {
+            return Ok((fields, Some(field)));
+        }
+
+        let name = field.name().unwrap_or_default().to_ascii_lowercase();
+        let mut value = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(invalid_form)? {
+            if value.len() + chunk.len() > MAX_FIELD_LENGTH {
+                return Err(invalid_form(format!(
+                    "field {} is longer than {} bytes",
+                    name, MAX_FIELD_LENGTH
+                )));
+            }
+            value.extend_from_slice(&chunk);
+        }
+        let value = String::from_utf8(value)
+            .map_err(|_| invalid_form(format!("field {} is not utf-8", name)))?;
+        fields.insert(name, value);
+    }
+
+    Ok((fields, None))
+}
+
+/// the object name of a file, the `key` field with `${filename}` replaced
+/// or the filename itself if there is no `key`
+pub fn object_name(fields: &HashMap<String, String>, filename: &str) -> String {
+    match fields.get("key") {
+        Some(key) => key.replace("${filename}", filename),
+        None => filename.to_string(),
+    }
+}
+
+/// the values the conditions of a post policy are checked against for one file
+pub fn policy_fields(
+    fields: &HashMap<String, String>,
+    bucket_name: &str,
+    object_name: &str,
+    content_type: Option<&str>,
+) -> HashMap<String, String> {
+    let mut values: HashMap<String, String> = fields
+        .iter()
+        .filter(|(name, _)| !CREDENTIAL_FIELDS.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    values.insert("bucket".into(), bucket_name.into());
+    values.insert("key".into(), object_name.into());
+    values.insert(
+        "content-type".into(),
+        content_type.unwrap_or_default().into(),
+    );
+    values
+}
@@ -0,0 +1,63 @@
+use crate::backend::types::CreateObjectValidationError;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: u64 = 24 * 60 * 60;
+const HOUR: u64 = 60 * 60;
+
+/// rules the sweeper applies to the objects of a bucket
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LifecycleRules {
+    /// delete objects uploaded more than this many days ago
+    pub expire_after_days: Option<u64>,
+    pub prefix_rules: Vec<PrefixRule>,
+    /// remove uploads that did not complete within this many hours
+    pub abort_incomplete_after_hours: Option<u64>,
+}
+
+/// deletes the objects whose name starts with `prefix` after a number of days
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrefixRule {
+    pub prefix: String,
+    pub expire_after_days: u64,
+}
+
+/// the point in time `days` days before `now`
+pub fn days_ago(now: SystemTime, days: u64) -> SystemTime {
+    now.checked_sub(Duration::from_secs(days.saturating_mul(DAY)))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+/// the point in time `hours` hours before `now`
+pub fn hours_ago(now: SystemTime, hours: u64) -> SystemTime {
+    now.checked_sub(Duration::from_secs(hours.saturating_mul(HOUR)))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+/// parses the `x-expires-at` header, either seconds since the epoch or an http date,
+/// the time has to be in the future
+pub fn parse_expires_at(value: &str) -> Result<SystemTime, CreateObjectValidationError> {
+    let value = value.trim();
+    let invalid = || {
+        CreateObjectValidationError::InvalidExpiry(format!(
+            "x-expires-at must be seconds since the epoch or an http date, got {:?}",
+            value
+        ))
+    };
+    let expires_at = match value.parse::<u64>() {
+        Ok(seconds) => UNIX_EPOCH
+            .checked_add(Duration::from_secs(seconds))
+            .ok_or_else(invalid)?,
+        Err(_) => httpdate::parse_http_date(value).map_err(|_| invalid())?,
+    };
+
+    if expires_at <= SystemTime::now() {
+        return Err(CreateObjectValidationError::InvalidExpiry(String::from(
+            "x-expires-at is in the past",
+        )));
+    }
+
+    Ok(expires_at)
+}
@@ -0,0 +1,83 @@
+use super::{create_retention_index, internal_database, Client};
+use crate::backend::audit::{AuditQuery, AuditRecord};
+use crate::config::Config;
+
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::error::Error as MongoDBError;
+use mongodb::options::FindOptions;
+use mongodb::IndexModel;
+use std::time::Duration;
+
+pub const AUDIT_COLLECTION: &str = "audit";
+/// amount of records returned without a limit in the query
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// records are queried by organisation and removed `audit_retention` seconds after they were written
+pub async fn create_indexes(client: &Client) -> Result<(), MongoDBError> {
+    let db = client.database(internal_database());
+    let index = IndexModel::builder()
+        .keys(doc! {"organisation": 1, "timestamp": -1})
+        .build();
+    db.collection::<AuditRecord>(AUDIT_COLLECTION)
+        .create_index(index, None)
+        .await?;
+
+    create_retention_index(
+        &db,
+        AUDIT_COLLECTION,
+        Duration::from_secs(Config::global().audit_retention),
+    )
+    .await
+}
+
+pub async fn insert_record(client: &Client, record: &AuditRecord) -> Result<(), MongoDBError> {
+    let mut document = mongodb::bson::to_document(record)?;
+    // a date the ttl index can expire the record by
+    document.insert("recordedAt", DateTime::now());
+    client
+        .database(internal_database())
+        .collection::<Document>(AUDIT_COLLECTION)
+        .insert_one(document, None)
+        .await?;
+
+    Ok(())
+}
+
+/// the matching records, newest first
+pub async fn find_records(
+    client: &Client,
+    query: &AuditQuery,
+) -> Result<Vec<AuditRecord>, MongoDBError> {
+    let mut filter = Document::new();
+    if let Some(organisation) = &query.organisation {
+        filter.insert("organisation", organisation);
+    }
+    if let Some(sub) = &query.sub {
+        filter.insert("sub", sub);
+    }
+    let mut timestamp = Document::new();
+    if let Some(from) = query.from {
+        timestamp.insert("$gte", from);
+    }
+    if let Some(to) = query.to {
+        timestamp.insert("$lte", to);
+    }
+    if !timestamp.is_empty() {
+        filter.insert("timestamp", timestamp);
+    }
+
+    let options = FindOptions::builder()
+        .sort(doc! {"timestamp": -1})
+        .limit(query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+        .build();
+
+    client
+        .database(internal_database())
+        .collection::<AuditRecord>(AUDIT_COLLECTION)
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await
+}
@@ -0,0 +1,259 @@
+use super::expiry::object_id_at;
+use super::{get_u64, is_duplicate_key_error};
+
+use futures::stream::{Stream, TryStreamExt};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error as MongoDBError;
+use mongodb::options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument};
+use mongodb::{Database, IndexModel};
+use mongodb_gridfs::options::GridFSBucketOptions;
+use mongodb_gridfs::GridFSBucket;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// GridFS bucket in every organisation database holding the deduplicated content,
+/// each blob is stored with its `blob_key` as filename
+pub const BLOBS_BUCKET: &str = "_blobs";
+const DEFAULT_CHUNK_SIZE: u64 = 255 * 1024;
+/// pending uploads older than this were interrupted and are removed by the sweeper
+const PENDING_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub fn blobs_bucket(db: Database) -> GridFSBucket {
+    let bucket_options = GridFSBucketOptions::builder()
+        .bucket_name(BLOBS_BUCKET.to_string())
+        .build();
+    GridFSBucket::new(db, Some(bucket_options))
+}
+
+/// the blobs are looked up by their key, which has to be unique for concurrent uploads
+/// to find each other, created with the first bucket that deduplicates
+pub async fn create_index(db: &Database) -> Result<(), MongoDBError> {
+    let unique_index = IndexOptions::builder().unique(true).build();
+    let index = IndexModel::builder()
+        .keys(doc! {"filename": 1})
+        .options(unique_index)
+        .build();
+    db.collection::<Document>(&format!("{}.files", BLOBS_BUCKET))
+        .create_index(index, None)
+        .await?;
+
+    Ok(())
+}
+
+/// filename used for the blob while it is uploaded and its sha256 is not known yet
+pub fn pending_filename() -> String {
+    format!("pending/{}", ObjectId::new().to_hex())
+}
+
+/// the name of the blob with the content `sha256` stored the way `metadata` says,
+/// compressed or encrypted content is only shared with objects stored the same way
+pub fn blob_key(sha256: &str, metadata: &Document) -> String {
+    let mut key = sha256.to_string();
+    if let Ok(compression) = metadata.get_str("compression") {
+        key.push('.');
+        key.push_str(compression);
+    }
+    if metadata.contains_key("encryption") {
+        key.push_str(".encrypted");
+    }
+    key
+}
+
+/// key of the blob an object refers to, `None` for objects stored with their own chunks
+pub fn blob_reference(object_doc: &Document) -> Option<&str> {
+    object_doc
+        .get_document("metadata")
+        .and_then(|metadata| metadata.get_str("blob"))
+        .ok()
+}
+
+async fn add_reference(
+    files: &mongodb::Collection<Document>,
+    key: &str,
+) -> Result<Option<Document>, MongoDBError> {
+    files
+        .find_one_and_update(
+            doc! {"filename": key},
+            doc! {"$inc": {"metadata.references": 1_i64}},
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+}
+
+/// turns the pending upload into the blob for `key`,
+/// or removes it and adds a reference to the blob that already has this content,
+/// returns the files document of the blob
+pub async fn store_blob(
+    db: &Database,
+    pending_id: ObjectId,
+    key: &str,
+) -> Result<Document, MongoDBError> {
+    let files = db.collection::<Document>(&format!("{}.files", BLOBS_BUCKET));
+
+    loop {
+        if let Some(blob) = add_reference(&files, key).await? {
+            blobs_bucket(db.clone()).delete(pending_id).await.ok();
+            return Ok(blob);
+        }
+
+        match files
+            .find_one_and_update(
+                doc! {"_id": pending_id},
+                doc! {"$set": {"filename": key, "metadata.references": 1_i64}},
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+        {
+            Ok(Some(blob)) => return Ok(blob),
+            Ok(None) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("pending upload of blob {} is gone", key),
+                )
+                .into())
+            }
+            // stored by a concurrent upload in the meantime, reference that one instead
+            Err(e) if is_duplicate_key_error(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// removes a reference to the blob, the chunks are freed when the last reference goes
+pub async fn release_blob(db: &Database, key: &str) -> Result<(), MongoDBError> {
+    let files = db.collection::<Document>(&format!("{}.files", BLOBS_BUCKET));
+    let chunks = db.collection::<Document>(&format!("{}.chunks", BLOBS_BUCKET));
+
+    files
+        .update_one(
+            doc! {"filename": key},
+            doc! {"$inc": {"metadata.references": -1_i64}},
+            None,
+        )
+        .await?;
+
+    // only matches if no upload added a reference in the meantime
+    if let Some(blob) = files
+        .find_one_and_delete(
+            doc! {"filename": key, "metadata.references": {"$lte": 0_i64}},
+            None,
+        )
+        .await?
+    {
+        let id = blob.get_object_id("_id").expect("all documentent have _id");
+        chunks.delete_many(doc! {"files_id": id}, None).await?;
+    }
+
+    Ok(())
+}
+
+/// stores the object as a files document without chunks that refers to the blob,
+/// the length and the keys of its encryption are taken over from the blob
+pub async fn insert_record(
+    db: &Database,
+    bucket_name: &str,
+    object_name: &str,
+    blob: &Document,
+    mut metadata: Document,
+) -> Result<(), MongoDBError> {
+    metadata.insert("blob", blob.get_str("filename").unwrap_or_default());
+    metadata.remove("compression");
+    metadata.remove("encryption");
+    if let Ok(blob_metadata) = blob.get_document("metadata") {
+        for key in ["compression", "encryption", "originalLength"] {
+            if let Some(value) = blob_metadata.get(key) {
+                metadata.insert(key, value.clone());
+            }
+        }
+    }
+
+    db.collection::<Document>(&format!("{}.files", bucket_name))
+        .insert_one(
+            doc! {
+                "filename": object_name,
+                "length": get_u64(blob, "length") as i64,
+                "chunkSize": DEFAULT_CHUNK_SIZE as i64,
+                "uploadDate": mongodb::bson::DateTime::now(),
+                "metadata": metadata,
+            },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// releases the blobs referenced by every object in the bucket
+pub async fn release_bucket(db: &Database, bucket_name: &str) -> Result<(), MongoDBError> {
+    let mut cursor = db
+        .collection::<Document>(&format!("{}.files", bucket_name))
+        .find(doc! {"metadata.blob": {"$exists": true}}, None)
+        .await?;
+
+    while let Some(object_doc) = cursor.try_next().await? {
+        if let Some(key) = blob_reference(&object_doc) {
+            release_blob(db, key).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// removes pending uploads that were never turned into a blob,
+/// e.g. because the process stopped during the upload, returns how many were removed
+pub async fn sweep_pending(db: &Database, now: SystemTime) -> Result<usize, MongoDBError> {
+    // the id of the files document is created when the upload starts
+    let cutoff = object_id_at(now.checked_sub(PENDING_TIMEOUT).unwrap_or(UNIX_EPOCH));
+    let bucket = blobs_bucket(db.clone());
+    let mut cursor = db
+        .collection::<Document>(&format!("{}.files", BLOBS_BUCKET))
+        .find(
+            doc! {
+                "filename": {"$regex": "^pending/"},
+                "_id": {"$lt": cutoff},
+            },
+            None,
+        )
+        .await?;
+
+    let mut removed = 0;
+    while let Some(pending) = cursor.try_next().await? {
+        let id = pending
+            .get_object_id("_id")
+            .expect("all documentent have _id");
+        match bucket.delete(id).await {
+            Ok(()) | Err(mongodb_gridfs::GridFSError::FileNotFound()) => removed += 1,
+            Err(mongodb_gridfs::GridFSError::MongoError(e)) => return Err(e),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// the files document of the blob
+pub async fn find_blob(db: &Database, key: &str) -> Result<Option<Document>, MongoDBError> {
+    db.collection::<Document>(&format!("{}.files", BLOBS_BUCKET))
+        .find_one(doc! {"filename": key}, None)
+        .await
+}
+
+/// streams the content of the blob
+pub async fn open_blob(
+    db: &Database,
+    key: &str,
+) -> Result<Option<impl Stream<Item = Vec<u8>>>, MongoDBError> {
+    let id = match find_blob(db, key).await? {
+        Some(blob) => blob.get_object_id("_id").expect("all documentent have _id"),
+        None => return Ok(None),
+    };
+
+    match blobs_bucket(db.clone()).open_download_stream(id).await {
+        Ok(stream) => Ok(Some(stream)),
+        Err(mongodb_gridfs::GridFSError::MongoError(e)) => Err(e),
+        Err(mongodb_gridfs::GridFSError::FileNotFound()) => Ok(None),
+    }
+}
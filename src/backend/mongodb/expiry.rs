@@ -0,0 +1,169 @@
+use super::{
+    dedup, internal_database, organisation_databases, organisation_of, remove_object, Bucket,
+    Client, BUCKET_COLLECTION,
+};
+use crate::backend::lifecycle::{self, LifecycleRules};
+
+use futures::stream::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::error::Error as MongoDBError;
+use mongodb::{Collection, Database};
+use mongodb_gridfs::options::GridFSBucketOptions;
+use mongodb_gridfs::GridFSBucket;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// matches filenames starting with `prefix`
+pub fn prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in prefix.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// smallest object id created at `time`, older ids compare lower
+pub fn object_id_at(time: SystemTime) -> ObjectId {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or_default();
+    let mut bytes = [0; 12];
+    bytes[..4].copy_from_slice(&seconds.to_be_bytes());
+    ObjectId::from_bytes(bytes)
+}
+
+async fn remove_matching(
+    db: &Database,
+    bucket_name: &str,
+    filter: Document,
+) -> Result<usize, MongoDBError> {
+    let bucket_options = GridFSBucketOptions::builder()
+        .bucket_name(bucket_name.to_string())
+        .build();
+    let bucket = GridFSBucket::new(db.clone(), Some(bucket_options));
+
+    let mut cursor = db
+        .collection::<Document>(&format!("{}.files", bucket_name))
+        .find(filter, None)
+        .await?;
+    let mut removed = 0;
+    while let Some(object_doc) = cursor.try_next().await? {
+        if remove_object(db, bucket_name, &bucket, &object_doc).await? {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+async fn sweep_bucket(
+    db: &Database,
+    bucket_name: &str,
+    rules: &LifecycleRules,
+    now: SystemTime,
+) -> Result<usize, MongoDBError> {
+    let mut expired = vec![doc! {"metadata.expiresAt": {"$lte": DateTime::from_system_time(now)}}];
+    if let Some(days) = rules.expire_after_days {
+        let cutoff = DateTime::from_system_time(lifecycle::days_ago(now, days));
+        expired.push(doc! {"uploadDate": {"$lt": cutoff}});
+    }
+    for rule in &rules.prefix_rules {
+        let cutoff = DateTime::from_system_time(lifecycle::days_ago(now, rule.expire_after_days));
+        expired.push(doc! {
+            "filename": {"$regex": prefix_pattern(&rule.prefix)},
+            "uploadDate": {"$lt": cutoff},
+        });
+    }
+
+    let mut removed = remove_matching(
+        db,
+        bucket_name,
+        doc! {"length": {"$exists": true}, "$or": expired},
+    )
+    .await?;
+
+    // incomplete uploads have no upload date yet, the object id tells when they started
+    if let Some(hours) = rules.abort_incomplete_after_hours {
+        let cutoff = object_id_at(lifecycle::hours_ago(now, hours));
+        removed += remove_matching(
+            db,
+            bucket_name,
+            doc! {"length": {"$exists": false}, "_id": {"$lt": cutoff}},
+        )
+        .await?;
+    }
+
+    Ok(removed)
+}
+
+/// applies the lifecycle rules of one bucket, or removes the interrupted uploads
+/// of the blobs bucket, returns the amount of removed objects
+async fn sweep_one(
+    buckets: &Collection<Bucket>,
+    db: &Database,
+    bucket_name: &str,
+    now: SystemTime,
+) -> Result<usize, MongoDBError> {
+    // blobs are removed with their last reference, only interrupted uploads are left
+    if bucket_name == dedup::BLOBS_BUCKET {
+        let pending = dedup::sweep_pending(db, now).await?;
+        if pending > 0 {
+            log::info!(
+                "sweeper removed {} interrupted uploads of {}",
+                pending,
+                organisation_of(db)
+            );
+        }
+        return Ok(0);
+    }
+
+    let rules = buckets
+        .find_one(doc! {"name": bucket_name}, None)
+        .await?
+        .map(|bucket| bucket.settings.lifecycle)
+        .unwrap_or_default();
+    sweep_bucket(db, bucket_name, &rules, now).await
+}
+
+/// applies the lifecycle rules and `x-expires-at` of every bucket,
+/// returns the amount of removed objects, a bucket that can not be swept is logged and skipped
+pub async fn sweep(client: &Client) -> Result<usize, MongoDBError> {
+    let buckets = client
+        .database(internal_database())
+        .collection::<Bucket>(BUCKET_COLLECTION);
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for database_name in organisation_databases(client).await? {
+        let db = client.database(&database_name);
+        let files_collections = match db
+            .list_collection_names(doc! {"name": {"$regex": "\\.files$"}})
+            .await
+        {
+            Ok(files_collections) => files_collections,
+            Err(e) => {
+                log::error!("can not list the buckets of {}: {}", database_name, e);
+                continue;
+            }
+        };
+
+        for files_collection in files_collections {
+            let bucket_name = files_collection.trim_end_matches(".files");
+            match sweep_one(&buckets, &db, bucket_name, now).await {
+                Ok(count) => removed += count,
+                Err(e) => log::error!(
+                    "can not sweep bucket {} of {}: {}",
+                    bucket_name,
+                    organisation_of(&db),
+                    e
+                ),
+            }
+        }
+    }
+
+    Ok(removed)
+}
@@ -0,0 +1,308 @@
+use super::{dedup, get_u64, internal_database, organisation_databases, organisation_of, Client};
+use crate::backend::checksum::{Checksums, ExpectedChecksums, Hasher};
+use crate::backend::compression::{self, Compression};
+use crate::backend::encryption::{self, EncryptionInfo, KeySource};
+use crate::backend::types::{ScrubProblem, ScrubProblemKind};
+
+use futures::stream::{StreamExt, TryStreamExt};
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error as MongoDBError;
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use warp::hyper::body::Bytes;
+
+pub const SCRUB_REPORTS_COLLECTION: &str = "scrub_reports";
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// re-reads the chunks of an object and compares them with the files document
+async fn scrub_object(
+    db: &Database,
+    chunks: &Collection<Document>,
+    file: &Document,
+) -> Result<Option<(ScrubProblemKind, String)>, MongoDBError> {
+    // the blob itself is checked when scrubbing the blobs bucket
+    if let Some(key) = dedup::blob_reference(file) {
+        let blob = db
+            .collection::<Document>(&format!("{}.files", dedup::BLOBS_BUCKET))
+            .find_one(doc! {"filename": key}, None)
+            .await?;
+
+        return Ok(blob.is_none().then(|| {
+            (
+                ScrubProblemKind::MissingChunks,
+                format!("blob {} is missing", key),
+            )
+        }));
+    }
+
+    let id = file.get_object_id("_id").expect("all documentent have _id");
+    let length = get_u64(file, "length");
+    let chunk_size = get_u64(file, "chunkSize");
+    let expected_chunks = if chunk_size == 0 {
+        0
+    } else {
+        length.div_ceil(chunk_size)
+    };
+
+    let metadata = file.get_document("metadata").ok();
+    let encryption_info = metadata
+        .and_then(|metadata| metadata.get_document("encryption").ok())
+        .and_then(|info| mongodb::bson::from_document::<EncryptionInfo>(info.clone()).ok());
+    // without the customer key only the chunks can be checked
+    let checksums = metadata
+        .and_then(|metadata| metadata.get_document("checksums").ok())
+        .and_then(|checksums| mongodb::bson::from_document::<Checksums>(checksums.clone()).ok())
+        .filter(|_| {
+            !encryption_info
+                .as_ref()
+                .is_some_and(|info| info.key_source == KeySource::Customer)
+        });
+    let hasher = Hasher::new(
+        checksums.as_ref().is_some_and(|x| x.md5.is_some()),
+        checksums.as_ref().is_some_and(|x| x.crc32c.is_some()),
+    );
+
+    let compression = metadata
+        .and_then(|metadata| metadata.get_str("compression").ok())
+        .and_then(Compression::from_name);
+    let transformed = compression.is_some() || encryption_info.is_some();
+
+    let options = FindOptions::builder().sort(doc! {"n": 1}).build();
+    let mut cursor = chunks.find(doc! {"files_id": id}, options.clone()).await?;
+    let mut n = 0;
+    let mut size = 0;
+    while let Some(chunk) = cursor.try_next().await? {
+        if get_u64(&chunk, "n") != n {
+            return Ok(Some((
+                ScrubProblemKind::MissingChunks,
+                format!("chunk {} is missing", n),
+            )));
+        }
+        let data = match chunk.get_binary_generic("data") {
+            Ok(data) => data,
+            Err(_) => {
+                return Ok(Some((
+                    ScrubProblemKind::MissingChunks,
+                    format!("chunk {} has no data", n),
+                )))
+            }
+        };
+        if !transformed {
+            hasher.update(data);
+        }
+        size += data.len() as u64;
+        n += 1;
+    }
+
+    if n != expected_chunks || size != length {
+        return Ok(Some((
+            ScrubProblemKind::MissingChunks,
+            format!(
+                "found {} of {} chunks with {} of {} bytes",
+                n, expected_chunks, size, length
+            ),
+        )));
+    }
+
+    // the checksums are computed over the data as it was uploaded
+    if transformed && checksums.is_some() {
+        let data_key = match encryption_info
+            .map(|info| encryption::object_key(&info, None))
+            .transpose()
+        {
+            Ok(data_key) => data_key,
+            Err(e) => {
+                return Ok(Some((
+                    ScrubProblemKind::ChecksumMismatch,
+                    format!("can not decrypt the data, {}", e),
+                )))
+            }
+        };
+
+        let mut stored = chunks
+            .find(doc! {"files_id": id}, options)
+            .await?
+            .map_err(std::io::Error::other)
+            .map_ok(|chunk| {
+                Bytes::from(
+                    chunk
+                        .get_binary_generic("data")
+                        .cloned()
+                        .unwrap_or_default(),
+                )
+            })
+            .boxed();
+        if let Some(data_key) = data_key {
+            stored = encryption::decrypt_stream(data_key, stored).boxed();
+        }
+        if let Some(compression) = compression {
+            stored = compression::decode_stream(compression, stored).boxed();
+        }
+
+        while let Some(data) = stored.next().await {
+            match data {
+                Ok(data) => hasher.update(&data),
+                Err(e) => {
+                    return Ok(Some((
+                        ScrubProblemKind::ChecksumMismatch,
+                        format!("can not read the stored data, {}", e),
+                    )))
+                }
+            }
+        }
+    }
+
+    if let Some(checksums) = checksums {
+        let expected = ExpectedChecksums {
+            sha256: Some(checksums.sha256),
+            md5: checksums.md5,
+            crc32c: checksums.crc32c,
+        };
+        if let Err(mismatch) = expected.verify(&hasher.finalize()) {
+            return Ok(Some((
+                ScrubProblemKind::ChecksumMismatch,
+                mismatch.to_string(),
+            )));
+        }
+    }
+
+    Ok(None)
+}
+
+async fn scrub_bucket(db: &Database, bucket_name: &str) -> Result<Vec<ScrubProblem>, MongoDBError> {
+    let files = db.collection::<Document>(&format!("{}.files", bucket_name));
+    let chunks = db.collection::<Document>(&format!("{}.chunks", bucket_name));
+    let mut problems = Vec::new();
+
+    // files without length are still being uploaded
+    let mut cursor = files.find(doc! {"length": {"$exists": true}}, None).await?;
+    while let Some(file) = cursor.try_next().await? {
+        if let Some((problem, detail)) = scrub_object(db, &chunks, &file).await? {
+            problems.push(ScrubProblem {
+                organisation: organisation_of(db).to_string(),
+                bucket: bucket_name.to_string(),
+                filename: file.get_str("filename").ok().map(String::from),
+                object_id: file
+                    .get_object_id("_id")
+                    .expect("all documentent have _id")
+                    .to_hex(),
+                problem,
+                detail,
+                detected_at: now(),
+            });
+        }
+    }
+
+    let mut cursor = chunks
+        .aggregate(
+            [
+                doc! {"$group": {"_id": "$files_id", "chunks": {"$sum": 1}}},
+                doc! {"$lookup": {
+                    "from": format!("{}.files", bucket_name),
+                    "localField": "_id",
+                    "foreignField": "_id",
+                    "as": "file",
+                }},
+                doc! {"$match": {"file": {"$size": 0}}},
+            ],
+            None,
+        )
+        .await?;
+    while let Some(orphan) = cursor.try_next().await? {
+        let object_id = match orphan.get_object_id("_id") {
+            Ok(id) => id.to_hex(),
+            Err(_) => format!("{}", orphan.get("_id").cloned().unwrap_or_default()),
+        };
+        problems.push(ScrubProblem {
+            organisation: organisation_of(db).to_string(),
+            bucket: bucket_name.to_string(),
+            filename: None,
+            object_id,
+            problem: ScrubProblemKind::OrphanedChunks,
+            detail: format!(
+                "{} chunks without files document",
+                get_u64(&orphan, "chunks")
+            ),
+            detected_at: now(),
+        });
+    }
+
+    Ok(problems)
+}
+
+/// scrubs the bucket and replaces its previous report, returns the number of problems
+async fn report_bucket(
+    reports: &Collection<ScrubProblem>,
+    db: &Database,
+    bucket_name: &str,
+) -> Result<usize, MongoDBError> {
+    let problems = scrub_bucket(db, bucket_name).await?;
+    let total = problems.len();
+
+    reports
+        .delete_many(
+            doc! {"organisation": organisation_of(db), "bucket": bucket_name},
+            None,
+        )
+        .await?;
+    if !problems.is_empty() {
+        reports.insert_many(problems, None).await?;
+    }
+
+    Ok(total)
+}
+
+/// checks every bucket of every organisation,
+/// the problems found replace the previous report of that bucket,
+/// a bucket that can not be scrubbed is logged and skipped
+pub async fn scrub(client: &Client) -> Result<usize, MongoDBError> {
+    let reports = client
+        .database(internal_database())
+        .collection::<ScrubProblem>(SCRUB_REPORTS_COLLECTION);
+    let mut total = 0;
+
+    for database_name in organisation_databases(client).await? {
+        let db = client.database(&database_name);
+        let files_collections = match db
+            .list_collection_names(doc! {"name": {"$regex": "\\.files$"}})
+            .await
+        {
+            Ok(files_collections) => files_collections,
+            Err(e) => {
+                log::error!("can not list the buckets of {}: {}", database_name, e);
+                continue;
+            }
+        };
+
+        for files_collection in files_collections {
+            let bucket_name = files_collection.trim_end_matches(".files");
+            match report_bucket(&reports, &db, bucket_name).await {
+                Ok(problems) => total += problems,
+                Err(e) => log::error!(
+                    "can not scrub bucket {} of {}: {}",
+                    bucket_name,
+                    organisation_of(&db),
+                    e
+                ),
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+pub async fn get_scrub_reports(client: &Client) -> Result<Vec<ScrubProblem>, MongoDBError> {
+    client
+        .database(internal_database())
+        .collection::<ScrubProblem>(SCRUB_REPORTS_COLLECTION)
+        .find(doc! {}, None)
+        .await?
+        .try_collect()
+        .await
+}
@@ -0,0 +1,142 @@
+use super::{create_retention_index, internal_database, Bucket, Client, BUCKET_COLLECTION};
+use crate::backend::webhooks::{Delivery, Webhook};
+use crate::config::Config;
+
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::error::Error as MongoDBError;
+use mongodb::options::FindOptions;
+use mongodb::Database;
+use std::time::Duration;
+
+pub const WEBHOOKS_COLLECTION: &str = "webhooks";
+pub const DELIVERIES_COLLECTION: &str = "webhook_deliveries";
+/// amount of deliveries returned by the delivery log
+const DELIVERY_LOG_SIZE: i64 = 100;
+
+/// deliveries are removed `webhook_delivery_retention` seconds after they were recorded
+pub async fn create_indexes(client: &Client) -> Result<(), MongoDBError> {
+    create_retention_index(
+        &client.database(internal_database()),
+        DELIVERIES_COLLECTION,
+        Duration::from_secs(Config::global().webhook_delivery_retention),
+    )
+    .await
+}
+
+/// stores the subscription, returns `None` if the organisation database `org_db` has no such bucket
+pub async fn add_webhook(
+    client: &Client,
+    org_db: &Database,
+    webhook: &Webhook,
+) -> Result<Option<String>, MongoDBError> {
+    let db = client.database(internal_database());
+    let bucket = db
+        .collection::<Bucket>(BUCKET_COLLECTION)
+        .find_one(doc! {"name": &webhook.bucket}, None)
+        .await?;
+    // bucket names are global, the files collection tells which organisation owns it
+    let owned = !org_db
+        .list_collection_names(doc! {"name": format!("{}.files", webhook.bucket)})
+        .await?
+        .is_empty();
+    if bucket.is_none() || !owned {
+        return Ok(None);
+    }
+
+    db.collection::<Webhook>(WEBHOOKS_COLLECTION)
+        .insert_one(webhook, None)
+        .await?;
+
+    Ok(Some(webhook.id.clone()))
+}
+
+pub async fn find_webhooks(
+    client: &Client,
+    organisation: &str,
+    bucket_name: &str,
+) -> Result<Vec<Webhook>, MongoDBError> {
+    client
+        .database(internal_database())
+        .collection::<Webhook>(WEBHOOKS_COLLECTION)
+        .find(
+            doc! {"organisation": organisation, "bucket": bucket_name},
+            None,
+        )
+        .await?
+        .try_collect()
+        .await
+}
+
+/// returns false if there is no such webhook
+pub async fn delete_webhook(
+    client: &Client,
+    organisation: &str,
+    bucket_name: &str,
+    id: &str,
+) -> Result<bool, MongoDBError> {
+    let result = client
+        .database(internal_database())
+        .collection::<Webhook>(WEBHOOKS_COLLECTION)
+        .delete_one(
+            doc! {"_id": id, "organisation": organisation, "bucket": bucket_name},
+            None,
+        )
+        .await?;
+
+    Ok(result.deleted_count == 1)
+}
+
+/// removes the webhooks of a deleted bucket with their deliveries
+pub async fn delete_bucket_webhooks(
+    client: &Client,
+    organisation: &str,
+    bucket_name: &str,
+) -> Result<(), MongoDBError> {
+    let db = client.database(internal_database());
+    let filter = doc! {"organisation": organisation, "bucket": bucket_name};
+    db.collection::<Webhook>(WEBHOOKS_COLLECTION)
+        .delete_many(filter.clone(), None)
+        .await?;
+    db.collection::<Delivery>(DELIVERIES_COLLECTION)
+        .delete_many(filter, None)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn record_delivery(client: &Client, delivery: &Delivery) -> Result<(), MongoDBError> {
+    let mut document = mongodb::bson::to_document(delivery)?;
+    // a date the ttl index can expire the delivery by
+    document.insert("recordedAt", DateTime::now());
+    client
+        .database(internal_database())
+        .collection::<Document>(DELIVERIES_COLLECTION)
+        .insert_one(document, None)
+        .await?;
+
+    Ok(())
+}
+
+/// the latest delivery attempts of the webhooks of a bucket, newest first
+pub async fn find_deliveries(
+    client: &Client,
+    organisation: &str,
+    bucket_name: &str,
+) -> Result<Vec<Delivery>, MongoDBError> {
+    let options = FindOptions::builder()
+        .sort(doc! {"timestamp": -1})
+        .limit(DELIVERY_LOG_SIZE)
+        .build();
+
+    client
+        .database(internal_database())
+        .collection::<Delivery>(DELIVERIES_COLLECTION)
+        .find(
+            doc! {"organisation": organisation, "bucket": bucket_name},
+            options,
+        )
+        .await?
+        .try_collect()
+        .await
+}
@@ -0,0 +1,85 @@
+use crate::backend::content_type::{self, ContentTypes};
+use crate::backend::types::CreateObjectValidationError;
+use serde::{Deserialize, Serialize};
+
+/// restrictions on the objects that can be stored in a bucket
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ObjectPolicy {
+    pub max_object_size: Option<u64>,
+    /// content type patterns like `image/*`, empty allows everything
+    pub allowed_content_types: Vec<String>,
+    pub denied_content_types: Vec<String>,
+    /// reject objects whose magic bytes don't match the declared content type
+    pub sniff_content_type: bool,
+}
+
+impl ObjectPolicy {
+    pub fn check_content_type(
+        &self,
+        content_type: &str,
+    ) -> Result<(), CreateObjectValidationError> {
+        let denied = self
+            .denied_content_types
+            .iter()
+            .any(|pattern| content_type::matches_pattern(content_type, pattern));
+        let allowed = self.allowed_content_types.is_empty()
+            || self
+                .allowed_content_types
+                .iter()
+                .any(|pattern| content_type::matches_pattern(content_type, pattern));
+
+        if denied || !allowed {
+            Err(CreateObjectValidationError::UnsupportedMediaType(format!(
+                "content type {} is not allowed in this bucket",
+                content_type::essence(content_type)
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// checks the request headers against the policy before reading the body
+    pub fn check(
+        &self,
+        content_type: Option<&str>,
+        content_length: Option<u64>,
+    ) -> Result<(), CreateObjectValidationError> {
+        if let Some(content_type) = content_type {
+            self.check_content_type(content_type)?;
+        }
+
+        match (self.max_object_size, content_length) {
+            (Some(max_object_size), Some(content_length)) if content_length > max_object_size => {
+                Err(CreateObjectValidationError::PayloadTooLarge(format!(
+                    "object of {} bytes exceeds the maximum object size of {} bytes",
+                    content_length, max_object_size
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// checks the content types detected from the start of the data
+    pub fn check_detected(
+        &self,
+        content_types: &ContentTypes,
+    ) -> Result<(), CreateObjectValidationError> {
+        self.check_content_type(&content_types.content_type())?;
+
+        if !self.sniff_content_type {
+            return Ok(());
+        }
+
+        match (&content_types.declared, content_types.sniffed) {
+            (Some(declared), Some(sniffed)) if sniffed != content_type::essence(declared) => {
+                Err(CreateObjectValidationError::UnsupportedMediaType(format!(
+                    "content type {} does not match the detected content type {}",
+                    content_type::essence(declared),
+                    sniffed
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
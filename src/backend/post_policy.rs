@@ -0,0 +1,171 @@
+use crate::backend::types::CreateObjectValidationError;
+use crate::config::Config;
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+
+/// the policy as sent, base64 encoded in the `policy` field of the form
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyDocument {
+    /// seconds since the epoch
+    expiration: u64,
+    #[serde(default)]
+    conditions: Vec<Value>,
+}
+
+#[derive(Debug)]
+enum Condition {
+    Eq(String, String),
+    StartsWith(String, String),
+    ContentLengthRange(u64, u64),
+}
+
+impl Condition {
+    /// `{"<field>": "<value>"}`, `["eq", "$<field>", "<value>"]`,
+    /// `["starts-with", "$<field>", "<prefix>"]` or `["content-length-range", <min>, <max>]`
+    fn parse(condition: &Value) -> Result<Condition, String> {
+        let invalid = || format!("invalid condition {}", condition);
+        let field = |name: &Value| match name.as_str().and_then(|name| name.strip_prefix('$')) {
+            Some(name) => Ok(name.to_ascii_lowercase()),
+            None => Err(invalid()),
+        };
+
+        match condition {
+            Value::Object(map) if map.len() == 1 => {
+                let (name, value) = map.iter().next().expect("map has one entry");
+                let value = value.as_str().ok_or_else(invalid)?;
+                Ok(Condition::Eq(name.to_ascii_lowercase(), value.to_string()))
+            }
+            Value::Array(items) => match items.as_slice() {
+                [Value::String(operator), name, Value::String(value)] => {
+                    match operator.to_ascii_lowercase().as_str() {
+                        "eq" => Ok(Condition::Eq(field(name)?, value.clone())),
+                        "starts-with" => Ok(Condition::StartsWith(field(name)?, value.clone())),
+                        _ => Err(invalid()),
+                    }
+                }
+                [Value::String(operator), min, max] if operator == "content-length-range" => {
+                    match (min.as_u64(), max.as_u64()) {
+                        (Some(min), Some(max)) if min <= max => {
+                            Ok(Condition::ContentLengthRange(min, max))
+                        }
+                        _ => Err(invalid()),
+                    }
+                }
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// what an upload signed by a keypair may store, like the post policies of s3,
+/// lets browsers upload without a token of their own
+#[derive(Debug)]
+pub struct PostPolicy {
+    /// the only bucket the policy allows uploads to
+    bucket: String,
+    conditions: Vec<Condition>,
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+impl PostPolicy {
+    /// `signature` is the hex encoded hmac-sha256 of the base64 encoded `policy`
+    /// with the secret of the keypair, the policy has to expire within
+    /// `post_policy_max_expiration` and name its bucket with an `eq` condition
+    pub fn verify(policy: &str, signature: &str, secret: &str) -> Result<PostPolicy, String> {
+        let signature = hex::decode(signature).map_err(|_| "signature is not hex encoded")?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("hmac accepts keys of any size");
+        mac.update(policy.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| "signature does not match the policy")?;
+
+        let document = base64::decode(policy).map_err(|e| format!("invalid policy, {}", e))?;
+        let document: PolicyDocument =
+            serde_json::from_slice(&document).map_err(|e| format!("invalid policy, {}", e))?;
+        if document.expiration <= now() {
+            return Err(String::from("policy has expired"));
+        }
+        let max_expiration = Config::global().post_policy_max_expiration;
+        if document.expiration > now().saturating_add(max_expiration) {
+            return Err(format!(
+                "policy expires too late, at most {} seconds ahead",
+                max_expiration
+            ));
+        }
+        let conditions: Vec<Condition> = document
+            .conditions
+            .iter()
+            .map(Condition::parse)
+            .collect::<Result<_, _>>()?;
+        let bucket = conditions
+            .iter()
+            .find_map(|condition| match condition {
+                Condition::Eq(name, bucket) if name == "bucket" => Some(bucket.clone()),
+                _ => None,
+            })
+            .ok_or("policy has no condition on the bucket")?;
+
+        Ok(PostPolicy { bucket, conditions })
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// checks every condition but the length of a file of the form, `fields` are the text fields
+    /// together with `bucket`, `key` as the object name and `content-type` of the file
+    pub fn check(
+        &self,
+        fields: &HashMap<String, String>,
+    ) -> Result<(), CreateObjectValidationError> {
+        let value = |name: &str| fields.get(name).map(String::as_str).unwrap_or_default();
+
+        for condition in &self.conditions {
+            let satisfied = match condition {
+                Condition::Eq(name, expected) => value(name) == expected,
+                Condition::StartsWith(name, prefix) => value(name).starts_with(prefix.as_str()),
+                Condition::ContentLengthRange(..) => true,
+            };
+            if !satisfied {
+                return Err(CreateObjectValidationError::PolicyViolation(format!(
+                    "{} does not satisfy the policy",
+                    name_of(condition)
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// the smallest and largest file the policy allows
+    pub fn length_range(&self) -> (Option<u64>, Option<u64>) {
+        self.conditions
+            .iter()
+            .fold((None, None), |(min, max), condition| match condition {
+                Condition::ContentLengthRange(low, high) => (
+                    Some(min.map_or(*low, |min: u64| min.max(*low))),
+                    Some(max.map_or(*high, |max: u64| max.min(*high))),
+                ),
+                _ => (min, max),
+            })
+    }
+}
+
+fn name_of(condition: &Condition) -> &str {
+    match condition {
+        Condition::Eq(name, _) | Condition::StartsWith(name, _) => name,
+        Condition::ContentLengthRange(..) => "content length",
+    }
+}
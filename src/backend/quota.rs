@@ -0,0 +1,116 @@
+use crate::backend::types::CreateObjectValidationError;
+use serde::{Deserialize, Serialize};
+
+/// limits on the amount of data an organisation or bucket can hold,
+/// `None` means unlimited
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_objects: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Usage {
+    pub bytes: u64,
+    pub objects: u64,
+}
+
+/// usage together with the quota it is checked against
+#[derive(Debug, Serialize)]
+pub struct QuotaUsage {
+    #[serde(flatten)]
+    pub usage: Usage,
+    #[serde(flatten)]
+    pub quota: Quota,
+}
+
+/// raised inside the upload stream when the quota is crossed mid-stream
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub reason: String,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+impl Quota {
+    /// bytes that can still be stored, `None` if unlimited
+    pub fn remaining_bytes(&self, usage: &Usage) -> Option<u64> {
+        self.max_bytes
+            .map(|max_bytes| max_bytes.saturating_sub(usage.bytes))
+    }
+
+    /// checks if a new object fits in the quota,
+    /// returns the amount of bytes the upload is allowed to use
+    pub fn check(
+        &self,
+        scope: &str,
+        usage: &Usage,
+        content_length: Option<u64>,
+    ) -> Result<Option<u64>, CreateObjectValidationError> {
+        if let Some(max_objects) = self.max_objects {
+            if usage.objects >= max_objects {
+                return Err(CreateObjectValidationError::QuotaExceeded(format!(
+                    "{} object quota of {} objects reached",
+                    scope, max_objects
+                )));
+            }
+        }
+
+        let remaining = self.remaining_bytes(usage);
+        match (remaining, content_length) {
+            (Some(0), _) => Err(CreateObjectValidationError::QuotaExceeded(format!(
+                "{} byte quota of {} bytes reached",
+                scope,
+                self.max_bytes.unwrap_or_default()
+            ))),
+            (Some(remaining), Some(content_length)) if content_length > remaining => {
+                Err(CreateObjectValidationError::PayloadTooLarge(format!(
+                    "object of {} bytes exceeds the remaining {} quota of {} bytes",
+                    content_length, scope, remaining
+                )))
+            }
+            _ => Ok(remaining),
+        }
+    }
+}
+
+/// smallest of two optional limits
+pub fn min_limit(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// wraps the upload stream and errors with `QuotaExceeded` as soon as more than `limit` bytes are read
+pub fn limit_stream<B: warp::Buf>(
+    stream: impl futures::Stream<Item = Result<B, std::io::Error>>,
+    limit: Option<u64>,
+) -> impl futures::Stream<Item = Result<B, std::io::Error>> {
+    use futures::stream::StreamExt;
+
+    let mut total: u64 = 0;
+    stream.map(move |item| {
+        let buffer = item?;
+        total += buffer.remaining() as u64;
+        match limit {
+            Some(limit) if total > limit => Err(std::io::Error::other(QuotaExceeded {
+                reason: format!("object exceeds the allowed size of {} bytes", limit),
+            })),
+            _ => Ok(buffer),
+        }
+    })
+}
+
+/// returns the `QuotaExceeded` error if that is what aborted the upload
+pub fn find_quota_exceeded(error: &std::io::Error) -> Option<&QuotaExceeded> {
+    error.get_ref()?.downcast_ref::<QuotaExceeded>()
+}
@@ -0,0 +1,96 @@
+use futures::stream::{Stream, StreamExt};
+use warp::hyper::body::Bytes;
+
+/// the bytes `start..=end` of the object as it is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// value of the `Content-Range` header of the partial response
+    pub fn content_range(&self, length: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, length)
+    }
+}
+
+/// parses the `Range` header for an object of `length` bytes,
+/// only a single range of bytes is served, other ranges get the whole object
+pub fn parse(range: Option<&str>, length: u64) -> RangeRequest {
+    let spec = match range.and_then(|range| range.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return RangeRequest::Full,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return RangeRequest::Full,
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // the last `suffix` bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 || length == 0 {
+                return RangeRequest::Unsatisfiable;
+            }
+            ByteRange {
+                start: length.saturating_sub(suffix),
+                end: length - 1,
+            }
+        }
+        (Ok(start), Err(_)) if end.is_empty() => ByteRange {
+            start,
+            end: length.saturating_sub(1),
+        },
+        (Ok(start), Ok(end)) if start <= end => ByteRange {
+            start,
+            end: end.min(length.saturating_sub(1)),
+        },
+        _ => return RangeRequest::Full,
+    };
+
+    if range.start >= length {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(range)
+    }
+}
+
+/// passes on `take` bytes of the stream after skipping the first `skip` bytes
+pub fn slice<S>(
+    stream: S,
+    skip: u64,
+    take: u64,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>>,
+{
+    let end = skip + take;
+    stream
+        // ends the stream once the last byte of the range was passed on
+        .scan(0, move |position: &mut u64, data| {
+            if *position >= end {
+                return futures::future::ready(None);
+            }
+            let data = data.map(|data| {
+                let chunk_start = *position;
+                *position += data.len() as u64;
+                let from = skip.saturating_sub(chunk_start).min(data.len() as u64);
+                let to = end.saturating_sub(chunk_start).min(data.len() as u64);
+                data.slice(from as usize..to as usize)
+            });
+            futures::future::ready(Some(data))
+        })
+        .filter(|data| futures::future::ready(!matches!(data, Ok(data) if data.is_empty())))
+}
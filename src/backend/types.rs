@@ -1,19 +1,26 @@
+use crate::backend::audit::AuditRecord;
+use crate::backend::encryption::EncryptionError;
+use crate::backend::error::ApiError;
+use crate::backend::quota::QuotaUsage;
+use crate::backend::webhooks::{Delivery, WebhookInfo};
+use serde::{Deserialize, Serialize};
 use warp::http::header::{HeaderValue, CONTENT_TYPE};
-use warp::http::StatusCode;
-use warp::reject::{Reject, Rejection};
+use warp::reject::Rejection;
 use warp::reply::Response;
 
-#[derive(Debug)]
-/// error that always raises
-struct CustomError {
-    info: String,
+/// create 500 internal server error, `info` is only logged
+pub fn raises(info: String) -> Rejection {
+    ApiError::Internal(info).reject()
 }
 
-impl Reject for CustomError {}
+/// the json response of a successful operation
+fn json_response(body: serde_json::Value) -> Response {
+    let mut response = Response::new(body.to_string().into());
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
-/// create 500 internal server error
-pub fn raises(info: String) -> Rejection {
-    warp::reject::custom(CustomError { info })
+    response
 }
 
 #[derive(Debug)]
@@ -25,172 +32,357 @@ pub struct CreateBucketResult {
 
 impl warp::Reply for CreateBucketResult {
     fn into_response(self) -> warp::reply::Response {
-        let is_validation_error = self.validation_error.is_some();
-        let message = if let Some(validation_error) = self.validation_error {
-            format!(
-                r#"{{"created": {}, "error": {:?}}}"#,
-                self.created, validation_error
-            )
-        } else {
-            let info = if self.created {
-                "OK"
-            } else {
-                "Bucket already exists"
-            };
-
-            format!(
-                r#"{{"bucket": {:?}, "created": {}, "info": {:?}}}"#,
-                self.bucket, self.created, info
-            )
-        };
-
-        let mut response = Response::new(message.into());
-        response
-            .headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-
-        if self.created {
-            *response.status_mut() = StatusCode::OK;
-        } else if !is_validation_error {
-            *response.status_mut() = StatusCode::CONFLICT;
-        } else {
-            *response.status_mut() = StatusCode::BAD_REQUEST;
+        let fields = serde_json::json!({"bucket": self.bucket, "created": self.created});
+        match self.validation_error {
+            Some(validation_error) => ApiError::InvalidBucket(validation_error).with_fields(fields),
+            None if !self.created => ApiError::BucketAlreadyExists.with_fields(fields),
+            None => json_response(
+                serde_json::json!({"bucket": self.bucket, "created": true, "info": "OK"}),
+            ),
         }
-
-        response
     }
 }
 
 #[derive(Debug)]
 pub struct DeleteBucketResult {
     pub bucket: String,
-    pub message: Option<&'static str>,
+    pub error: Option<ApiError>,
 }
 
 impl warp::Reply for DeleteBucketResult {
     fn into_response(self) -> warp::reply::Response {
-        let info = if let Some(message) = self.message {
-            message
-        } else {
-            "OK"
-        };
+        match self.error {
+            Some(error) => error.with_fields(serde_json::json!({"bucket": self.bucket})),
+            None => json_response(serde_json::json!({"bucket": self.bucket, "info": "OK"})),
+        }
+    }
+}
 
-        let message = format!(r#"{{"bucket": {:?}, "info": {:?}}}"#, self.bucket, info);
+#[derive(Debug, Clone)]
+pub enum CreateObjectValidationError {
+    BucketNotFound,
+    QuotaExceeded(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    ChecksumMismatch(String),
+    InvalidEncryptionKey(String),
+    InvalidExpiry(String),
+    /// the object does not satisfy the post policy of a form upload
+    PolicyViolation(String),
+    /// the token of a form upload does not allow the object
+    Unauthorised(String),
+}
 
-        let mut response = Response::new(message.into());
-        response
-            .headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+#[derive(Debug)]
+pub struct CreateObjectResult {
+    pub created: bool,
+    pub bucket: String,
+    pub filename: String,
+    pub validation_error: Option<CreateObjectValidationError>,
+}
 
-        if self.message.is_some() {
-            *response.status_mut() = StatusCode::BAD_REQUEST;
-        } else {
-            *response.status_mut() = StatusCode::OK;
-        };
+impl CreateObjectResult {
+    pub fn rejected(
+        bucket: String,
+        filename: String,
+        validation_error: CreateObjectValidationError,
+    ) -> CreateObjectResult {
+        CreateObjectResult {
+            created: false,
+            bucket,
+            filename,
+            validation_error: Some(validation_error),
+        }
+    }
+}
 
-        response
+impl CreateObjectResult {
+    /// why the object was not created
+    fn error(&self) -> Option<ApiError> {
+        match &self.validation_error {
+            Some(validation_error) => Some(ApiError::from(validation_error.clone())),
+            None if !self.created => Some(ApiError::ObjectAlreadyExists),
+            None => None,
+        }
+    }
+
+    /// the outcome as one entry of a form upload
+    fn summary(&self) -> serde_json::Value {
+        match self.error() {
+            Some(error) => serde_json::json!({
+                "filename": self.filename,
+                "created": false,
+                "code": error.code(),
+                "error": error.to_string(),
+            }),
+            None => serde_json::json!({
+                "filename": self.filename,
+                "created": true,
+            }),
+        }
     }
 }
 
-#[derive(Debug)]
-pub enum CreateObjectValidationError {
-    BucketNotFound,
+pub struct FormUploadResult {
+    pub bucket: String,
+    pub objects: Vec<CreateObjectResult>,
 }
 
-impl std::fmt::Display for CreateObjectValidationError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            CreateObjectValidationError::BucketNotFound => write!(f, "Bucket not found"),
+impl warp::Reply for FormUploadResult {
+    fn into_response(self) -> warp::reply::Response {
+        if self.objects.is_empty() {
+            return ApiError::InvalidRequest(String::from("form contains no file"))
+                .with_fields(serde_json::json!({"bucket": self.bucket}));
+        }
+
+        let body = serde_json::json!({
+            "bucket": self.bucket,
+            "objects": self
+                .objects
+                .iter()
+                .map(CreateObjectResult::summary)
+                .collect::<Vec<_>>(),
+        });
+        // none of the files was stored, the first one decides the status
+        let errors: Option<Vec<ApiError>> =
+            self.objects.iter().map(CreateObjectResult::error).collect();
+        match errors {
+            Some(mut errors) => errors.swap_remove(0).with_fields(body),
+            None => json_response(body),
+        }
+    }
+}
+
+impl warp::Reply for CreateObjectResult {
+    fn into_response(self) -> warp::reply::Response {
+        let fields = serde_json::json!({
+            "bucket": self.bucket,
+            "filename": self.filename,
+            "created": self.created,
+        });
+        match self.validation_error {
+            Some(validation_error) => ApiError::from(validation_error).with_fields(fields),
+            None if !self.created => ApiError::ObjectAlreadyExists.with_fields(fields),
+            None => json_response(serde_json::json!({
+                "bucket": self.bucket,
+                "filename": self.filename,
+                "created": true,
+                "info": "OK",
+            })),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct CreateObjectResult {
-    pub created: bool,
+pub struct DeleteObjectResult {
     pub bucket: String,
     pub filename: String,
-    pub validation_error: Option<CreateObjectValidationError>,
+    pub error: Option<ApiError>,
 }
 
-impl warp::Reply for CreateObjectResult {
+impl warp::Reply for DeleteObjectResult {
     fn into_response(self) -> warp::reply::Response {
-        let message = if let Some(validation_error) = &self.validation_error {
-            format!(
-                r#"{{"created": {}, "error": {:?}}}"#,
-                self.created, validation_error
-            )
-        } else {
-            let info = if self.created {
-                "OK"
-            } else {
-                "Object already exists"
-            };
-
-            format!(
-                r#"{{"bucket": {:?}, "filename": {:?}, "created": {}, "info": {:?}}}"#,
-                self.bucket, self.filename, self.created, info
-            )
-        };
-
-        let mut response = Response::new(message.into());
-        response
-            .headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-
-        match self.validation_error {
-            None if self.created => {
-                *response.status_mut() = StatusCode::OK;
-            }
+        let mut body = serde_json::json!({"bucket": self.bucket, "filename": self.filename});
+        match self.error {
+            Some(error) => error.with_fields(body),
             None => {
-                *response.status_mut() = StatusCode::CONFLICT;
-            }
-            Some(CreateObjectValidationError::BucketNotFound) => {
-                *response.status_mut() = StatusCode::NOT_FOUND;
+                body["info"] = "OK".into();
+                json_response(body)
             }
         }
+    }
+}
+
+/// outcome of every object of a batch delete
+#[derive(Debug)]
+pub struct DeleteObjectsResult {
+    pub bucket: String,
+    pub objects: Vec<DeleteObjectResult>,
+    /// more objects have the prefix than one request removes
+    pub truncated: bool,
+}
+
+impl warp::Reply for DeleteObjectsResult {
+    fn into_response(self) -> warp::reply::Response {
+        let objects: Vec<_> = self
+            .objects
+            .into_iter()
+            .map(|object| match object.error {
+                Some(error) => serde_json::json!({
+                    "filename": object.filename,
+                    "deleted": false,
+                    "code": error.code(),
+                    "error": error.to_string(),
+                }),
+                None => serde_json::json!({"filename": object.filename, "deleted": true}),
+            })
+            .collect();
 
-        response
+        json_response(serde_json::json!({
+            "bucket": self.bucket,
+            "objects": objects,
+            "truncated": self.truncated,
+        }))
     }
 }
 
+/// the key needed to read an encrypted object is missing or wrong
 #[derive(Debug)]
-pub struct DeleteObjectResult {
+pub struct ObjectKeyError {
     pub bucket: String,
     pub filename: String,
-    pub message: Option<&'static str>,
+    pub error: EncryptionError,
 }
 
-impl warp::Reply for DeleteObjectResult {
+impl warp::Reply for ObjectKeyError {
     fn into_response(self) -> warp::reply::Response {
-        let info = if let Some(message) = self.message {
-            message
-        } else {
-            "OK"
-        };
-
-        let message = format!(
-            r#"{{"bucket": {:?}, "filename": {:?}, "info": {:?}}}"#,
-            self.bucket, self.filename, info
-        );
-
-        let mut response = Response::new(message.into());
-        response
-            .headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-
-        match self.message {
-            Some("object not found") => {
-                *response.status_mut() = StatusCode::NOT_FOUND;
-            }
-            Some(_) => {
-                *response.status_mut() = StatusCode::BAD_REQUEST;
+        if let EncryptionError::MissingMasterKey = self.error {
+            log::error!(
+                "can not read {}/{}, {}",
+                self.bucket,
+                self.filename,
+                self.error
+            );
+        }
+
+        ApiError::from(self.error).with_fields(serde_json::json!({
+            "bucket": self.bucket,
+            "filename": self.filename,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct UsageResult {
+    pub bucket: String,
+    pub organisation: String,
+    pub bucket_usage: Option<QuotaUsage>,
+    pub organisation_usage: Option<QuotaUsage>,
+}
+
+impl warp::Reply for UsageResult {
+    fn into_response(self) -> warp::reply::Response {
+        if self.bucket_usage.is_none() {
+            return ApiError::BucketNotFound
+                .with_fields(serde_json::json!({"bucket": self.bucket}));
+        }
+
+        json_response(serde_json::json!({
+            "bucket": self.bucket,
+            "organisation": self.organisation,
+            "bucket_usage": self.bucket_usage,
+            "organisation_usage": self.organisation_usage,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubProblemKind {
+    /// chunks are missing or the stored length doesn't match the chunks
+    MissingChunks,
+    /// recomputed checksum differs from the checksum stored at upload
+    ChecksumMismatch,
+    /// chunks that belong to no files document
+    OrphanedChunks,
+}
+
+/// a corrupt or orphaned object found by the scrubber
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScrubProblem {
+    pub organisation: String,
+    pub bucket: String,
+    pub filename: Option<String>,
+    pub object_id: String,
+    pub problem: ScrubProblemKind,
+    pub detail: String,
+    /// unix timestamp in seconds
+    pub detected_at: i64,
+}
+
+#[derive(Debug)]
+pub struct ScrubReportResult {
+    pub problems: Vec<ScrubProblem>,
+}
+
+impl warp::Reply for ScrubReportResult {
+    fn into_response(self) -> warp::reply::Response {
+        json_response(serde_json::json!({ "problems": self.problems }))
+    }
+}
+
+#[derive(Debug)]
+pub struct CreateWebhookResult {
+    pub bucket: String,
+    /// id of the new webhook, `None` if the bucket does not exist
+    pub id: Option<String>,
+    pub validation_error: Option<String>,
+}
+
+impl warp::Reply for CreateWebhookResult {
+    fn into_response(self) -> warp::reply::Response {
+        let fields = serde_json::json!({"bucket": self.bucket});
+        match (self.id, self.validation_error) {
+            (_, Some(validation_error)) => {
+                ApiError::InvalidWebhook(validation_error).with_fields(fields)
             }
-            None => {
-                *response.status_mut() = StatusCode::OK;
+            (Some(id), None) => {
+                json_response(serde_json::json!({"bucket": self.bucket, "id": id, "info": "OK"}))
             }
+            (None, None) => ApiError::BucketNotFound.with_fields(fields),
         }
+    }
+}
 
-        response
+#[derive(Debug)]
+pub struct WebhookListResult {
+    pub bucket: String,
+    pub webhooks: Vec<WebhookInfo>,
+}
+
+impl warp::Reply for WebhookListResult {
+    fn into_response(self) -> warp::reply::Response {
+        json_response(serde_json::json!({"bucket": self.bucket, "webhooks": self.webhooks}))
+    }
+}
+
+#[derive(Debug)]
+pub struct DeleteWebhookResult {
+    pub bucket: String,
+    pub id: String,
+    pub deleted: bool,
+}
+
+impl warp::Reply for DeleteWebhookResult {
+    fn into_response(self) -> warp::reply::Response {
+        if self.deleted {
+            json_response(serde_json::json!({"bucket": self.bucket, "id": self.id, "info": "OK"}))
+        } else {
+            ApiError::WebhookNotFound
+                .with_fields(serde_json::json!({"bucket": self.bucket, "id": self.id}))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WebhookDeliveriesResult {
+    pub bucket: String,
+    pub deliveries: Vec<Delivery>,
+}
+
+impl warp::Reply for WebhookDeliveriesResult {
+    fn into_response(self) -> warp::reply::Response {
+        json_response(serde_json::json!({"bucket": self.bucket, "deliveries": self.deliveries}))
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditLogResult {
+    pub records: Vec<AuditRecord>,
+}
+
+impl warp::Reply for AuditLogResult {
+    fn into_response(self) -> warp::reply::Response {
+        json_response(serde_json::json!({ "records": self.records }))
     }
 }
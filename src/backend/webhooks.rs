@@ -0,0 +1,372 @@
+use crate::backend::encryption::{EncryptionError, EncryptionKey};
+use crate::backend::events::{Event, EventKind};
+use crate::backend::{implementation, Client};
+use crate::config::Config;
+use crate::metrics::time_operation;
+
+use hmac::{Hmac, Mac};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// the longest wait between two attempts to deliver an event
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+const EVENT_NAMES: [EventKind; 4] = [
+    EventKind::ObjectCreated,
+    EventKind::ObjectDeleted,
+    EventKind::BucketCreated,
+    EventKind::BucketDeleted,
+];
+
+/// body of the request registering a webhook
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookRequest {
+    pub url: String,
+    /// names of the events to deliver, empty delivers all of them
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// key of the HMAC-SHA256 signature sent in `x-webhook-signature`
+    pub secret: String,
+}
+
+impl WebhookRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        check_url(&self.url)?;
+
+        if let Some(unknown) = self
+            .events
+            .iter()
+            .find(|event| !EVENT_NAMES.iter().any(|kind| kind.name() == event.as_str()))
+        {
+            return Err(format!("unknown event {}", unknown));
+        }
+
+        if self.secret.is_empty() {
+            return Err(String::from("secret must not be empty"));
+        }
+
+        Ok(())
+    }
+
+    /// the subscription as it is stored, the secret sealed with the master key
+    pub fn into_webhook(
+        self,
+        organisation: &str,
+        bucket: &str,
+    ) -> Result<Webhook, EncryptionError> {
+        Ok(Webhook {
+            id: ObjectId::new().to_hex(),
+            organisation: organisation.to_string(),
+            bucket: bucket.to_string(),
+            url: self.url,
+            events: self.events,
+            sealed_secret: EncryptionKey::master()?.seal(self.secret.as_bytes())?,
+        })
+    }
+}
+
+/// stored webhook subscription of a bucket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub organisation: String,
+    pub bucket: String,
+    pub url: String,
+    pub events: Vec<String>,
+    /// signing secret encrypted with the master key
+    pub sealed_secret: String,
+}
+
+impl Webhook {
+    pub fn wants(&self, event_name: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|name| name == event_name)
+    }
+
+    fn secret(&self) -> Result<Vec<u8>, EncryptionError> {
+        EncryptionKey::master()?.open(&self.sealed_secret)
+    }
+}
+
+/// a webhook as it is listed, the signing secret is never returned
+#[derive(Debug, Serialize)]
+pub struct WebhookInfo {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<String>,
+}
+
+impl From<Webhook> for WebhookInfo {
+    fn from(webhook: Webhook) -> Self {
+        WebhookInfo {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+        }
+    }
+}
+
+/// webhooks are only delivered to public addresses, unless the host is in `webhook_allowed_hosts`
+fn check_url(url: &str) -> Result<(), String> {
+    let uri = url
+        .parse::<warp::http::Uri>()
+        .map_err(|e| format!("invalid url, {}", e))?;
+    let host = match uri.host() {
+        Some(host) if matches!(uri.scheme_str(), Some("http") | Some("https")) => {
+            host.trim_start_matches('[').trim_end_matches(']')
+        }
+        _ => return Err(String::from("url must be an absolute http or https url")),
+    };
+
+    let internal = host.eq_ignore_ascii_case("localhost")
+        || host.to_ascii_lowercase().ends_with(".localhost")
+        || matches!(host.parse::<IpAddr>(), Ok(ip) if is_internal(ip));
+    if internal && !is_allowed_host(host) {
+        return Err(format!("{} is not a public address", host));
+    }
+
+    Ok(())
+}
+
+fn is_allowed_host(host: &str) -> bool {
+    Config::global()
+        .webhook_allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+/// loopback, private, link-local and other addresses not reachable on the internet
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal_v4(ip),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local fc00::/7 and link-local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80
+            }
+        },
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || first == 0
+        // shared address space 100.64.0.0/10 and reserved 240.0.0.0/4
+        || (first == 100 && (second & 0xc0) == 64)
+        || first >= 240
+}
+
+/// resolves host names of webhooks, leaving out internal addresses
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: warp::hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let allowed = is_allowed_host(&host);
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| allowed || !is_internal(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// a single attempt to deliver an event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub webhook_id: String,
+    pub organisation: String,
+    pub bucket: String,
+    pub event: String,
+    pub url: String,
+    pub attempt: u32,
+    /// http status of the response, missing if the request failed
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub delivered: bool,
+    /// seconds since the epoch
+    pub timestamp: i64,
+}
+
+/// hex encoded HMAC-SHA256 of the payload, prefixed with `sha256=`
+pub fn sign(secret: &[u8], payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts keys of any size");
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// the client used to look up subscriptions and record deliveries
+pub fn setup(client: Client) {
+    CLIENT.set(client).ok();
+}
+
+/// delivers the event to the matching webhooks of its bucket in the background
+pub fn dispatch(event: &Event) {
+    let client = match CLIENT.get() {
+        Some(client) => client.clone(),
+        None => return,
+    };
+    let payload = match serde_json::to_vec(event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::error!("failed to serialize {} event: {}", event.event.name(), e);
+            return;
+        }
+    };
+    let organisation = event.organisation.clone();
+    let bucket = event.bucket.clone();
+    let event_name = event.event.name();
+
+    tokio::spawn(async move {
+        let webhooks = match time_operation(
+            "find_webhooks",
+            implementation::find_webhooks(&client, &organisation, &bucket),
+        )
+        .await
+        {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                log::error!("failed to look up webhooks of {}: {}", bucket, e);
+                return;
+            }
+        };
+
+        for webhook in webhooks
+            .into_iter()
+            .filter(|webhook| webhook.wants(event_name))
+        {
+            tokio::spawn(deliver(
+                client.clone(),
+                webhook,
+                event_name,
+                payload.clone(),
+            ));
+        }
+    });
+}
+
+/// posts the payload until the receiver answers with a success status,
+/// waiting twice as long after every failed attempt, but never longer than `MAX_BACKOFF`
+async fn deliver(client: Client, webhook: Webhook, event_name: &'static str, payload: Vec<u8>) {
+    let config = Config::global();
+    let http_client = HTTP_CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.webhook_timeout))
+            .dns_resolver(Arc::new(PublicResolver))
+            // a redirect could lead to an internal address
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("http client can be built")
+    });
+    // the allowed hosts may have changed since the webhook was registered
+    if let Err(e) = check_url(&webhook.url) {
+        log::warn!(
+            "not delivering {} to webhook {}: {}",
+            event_name,
+            webhook.id,
+            e
+        );
+        return;
+    }
+    let signature = match webhook.secret() {
+        Ok(secret) => sign(&secret, &payload),
+        Err(e) => {
+            log::error!("can not open the secret of webhook {}: {}", webhook.id, e);
+            return;
+        }
+    };
+    let mut backoff = Duration::from_millis(config.webhook_backoff_ms).min(MAX_BACKOFF);
+
+    for attempt in 1..=config.webhook_max_attempts {
+        let result = http_client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header(EVENT_HEADER, event_name)
+            .body(payload.clone())
+            .send()
+            .await;
+
+        let (status, error) = match result {
+            Ok(response) if response.status().is_success() => (Some(response.status()), None),
+            Ok(response) => (
+                Some(response.status()),
+                Some(format!("receiver answered {}", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let delivered = error.is_none();
+
+        let delivery = Delivery {
+            webhook_id: webhook.id.clone(),
+            organisation: webhook.organisation.clone(),
+            bucket: webhook.bucket.clone(),
+            event: event_name.to_string(),
+            url: webhook.url.clone(),
+            attempt,
+            status: status.map(|status| status.as_u16()),
+            error,
+            delivered,
+            timestamp: now(),
+        };
+        if let Err(e) = time_operation(
+            "record_webhook_delivery",
+            implementation::record_webhook_delivery(&client, &delivery),
+        )
+        .await
+        {
+            log::error!("failed to record webhook delivery: {}", e);
+        }
+
+        if delivered {
+            return;
+        }
+        if attempt < config.webhook_max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+        }
+    }
+
+    log::warn!(
+        "giving up delivering {} to {} after {} attempts",
+        event_name,
+        webhook.url,
+        config.webhook_max_attempts
+    );
+}
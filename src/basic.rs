@@ -1,17 +1,25 @@
 pub mod auth;
+pub mod cors;
 
 use warp::http::{Method, StatusCode};
 use warp::path::{param, tail};
 use warp::{Filter, Rejection};
 
-use crate::backend::{Client, Unauthorised};
-use crate::context::Context;
+use crate::backend::content_type;
+use crate::backend::error::ApiError;
+use crate::backend::{Client, DownloadHeaders, UploadHeaders};
+use crate::context::{self, Context};
+use crate::metrics;
+use crate::request_id::RequestId;
+use crate::server::PeerAddress;
+use crate::tls::ClientCertificate;
 
 const POST_METHOD: Method = warp::http::Method::POST;
 const GET_METHOD: Method = warp::http::Method::GET;
 const DELETE_METHOD: Method = warp::http::Method::DELETE;
+const HEAD_METHOD: Method = warp::http::Method::HEAD;
 
-fn with_base(
+pub fn with_base(
     client: Client,
     method: &'static Method,
 ) -> impl Filter<Extract = (Context,), Error = warp::Rejection> + Clone {
@@ -20,73 +28,263 @@ fn with_base(
         .map(move |client| (client, method))
         .untuple_one()
         .and(warp::header::optional::<String>("authorization"))
+        .and(warp::ext::optional::<ClientCertificate>())
+        .and(warp::ext::optional::<PeerAddress>())
+        .and(warp::ext::optional::<RequestId>())
         .then(Context::from_auth_header)
 }
 
-async fn handle_rejection(err: Rejection) -> Result<impl warp::Reply, std::convert::Infallible> {
-    if err.is_not_found() {
-        Ok(warp::reply::with_status(
-            "NOT_FOUND".to_string(),
-            StatusCode::NOT_FOUND,
-        ))
-    } else if let Some(e) = err.find::<Unauthorised>() {
-        Ok(warp::reply::with_status(
-            serde_json::json!({"error": e.reason}).to_string(),
-            StatusCode::UNAUTHORIZED,
-        ))
-    } else {
-        eprintln!("unhandled rejection: {:?}", err);
-        Ok(warp::reply::with_status(
-            "INTERNAL_SERVER_ERROR".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+/// refuses requests over the rate limits of their caller,
+/// after a route matched the request and before it reads the body
+pub fn within_limits() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::any()
+        .and_then(|| async { context::check_limits() })
+        .untuple_one()
+}
+
+/// only matches if the query string contains `flag`, like `?usage`
+fn query_flag(flag: &'static str) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(move |query: String| async move {
+            if query
+                .split('&')
+                .any(|pair| pair.split('=').next() == Some(flag))
+            {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+/// only matches `multipart/form-data` bodies, extracts the content type with its boundary
+fn multipart_form() -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("content-type").and_then(
+        |content_type: Option<String>| async move {
+            match content_type {
+                Some(content_type)
+                    if content_type::essence(&content_type) == "multipart/form-data" =>
+                {
+                    Ok(content_type)
+                }
+                _ => Err(warp::reject::not_found()),
+            }
+        },
+    )
+}
+
+/// the status `handle_rejection` answers with,
+/// `None` for rejections of requests that didn't match a route
+pub fn rejection_status(rejection: &Rejection) -> Option<StatusCode> {
+    rejection.find::<ApiError>().map(ApiError::status)
+}
+
+pub async fn handle_rejection(
+    err: Rejection,
+) -> Result<impl warp::Reply, std::convert::Infallible> {
+    let error = ApiError::from_rejection(&err);
+    match &error {
+        ApiError::Internal(info) => log::error!("internal error: {}", info),
+        ApiError::BackendUnavailable(info) => log::error!("backend unavailable: {}", info),
+        _ => (),
     }
+    metrics::rejection(error.code());
+
+    Ok(error)
 }
 
 pub fn basic_endpoint(client: Client) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
-    let create_bucket_endpoint = warp::any()
-        .and(with_base(client.clone(), &POST_METHOD))
-        .and(warp::filters::path::param::<String>())
-        .and(warp::path::end())
-        .and(warp::post())
-        .and_then(crate::backend::create_bucket);
-
-    let delete_bucket_endpoint = warp::any()
-        .and(with_base(client.clone(), &DELETE_METHOD))
-        .and(warp::filters::path::param::<String>())
-        .and(warp::query::<crate::backend::DeleteBucketOptions>())
-        .and(warp::path::end())
-        .and(warp::delete())
-        .and_then(crate::backend::delete_bucket);
-
-    let create_object_endpoint = warp::any()
-        .and(with_base(client.clone(), &POST_METHOD))
-        .and(param())
-        .and(tail())
-        .and(warp::post())
-        .and(warp::header::<String>("content-type"))
-        .and(warp::filters::body::stream())
-        .and_then(crate::backend::create_object);
-
-    let delete_object_endpoint = warp::any()
-        .and(with_base(client.clone(), &DELETE_METHOD))
-        .and(param())
-        .and(tail())
-        .and(warp::delete())
-        .and_then(crate::backend::delete_object);
-
-    let get_object_endpoint = warp::any()
-        .and(with_base(client, &GET_METHOD))
-        .and(param())
-        .and(tail())
-        .and(warp::get())
-        .and_then(crate::backend::get_object);
-
-    let basic_endpoint = create_bucket_endpoint
+    let create_webhook_endpoint = metrics::instrument(
+        "create_webhook",
+        warp::any()
+            .and(with_base(client.clone(), &POST_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(query_flag("webhooks"))
+            .and(warp::header::optional::<String>("content-type"))
+            .and(within_limits())
+            .and(warp::body::bytes())
+            .and_then(crate::backend::create_webhook),
+    );
+
+    let get_webhooks_endpoint = metrics::instrument(
+        "get_webhooks",
+        warp::any()
+            .and(with_base(client.clone(), &GET_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(query_flag("webhooks"))
+            .and(within_limits())
+            .and_then(crate::backend::get_webhooks),
+    );
+
+    let delete_webhook_endpoint = metrics::instrument(
+        "delete_webhook",
+        warp::any()
+            .and(with_base(client.clone(), &DELETE_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(query_flag("webhooks"))
+            .and(warp::query::<crate::backend::WebhookOptions>())
+            .and(within_limits())
+            .and_then(crate::backend::delete_webhook),
+    );
+
+    let webhook_deliveries_endpoint = metrics::instrument(
+        "webhook_deliveries",
+        warp::any()
+            .and(with_base(client.clone(), &GET_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(query_flag("webhook_deliveries"))
+            .and(within_limits())
+            .and_then(crate::backend::get_webhook_deliveries),
+    );
+
+    let delete_objects_endpoint = metrics::instrument(
+        "delete_objects",
+        warp::any()
+            .and(with_base(client.clone(), &POST_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(query_flag("delete"))
+            .and(warp::header::optional::<String>("content-type"))
+            .and(within_limits())
+            .and(warp::body::bytes())
+            .and_then(crate::backend::delete_objects),
+    );
+
+    let create_objects_from_form_endpoint = metrics::instrument(
+        "create_objects_from_form",
+        warp::any()
+            .and(with_base(client.clone(), &POST_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(multipart_form())
+            .and(within_limits())
+            .and(warp::filters::body::stream())
+            .and_then(crate::backend::create_objects_from_form),
+    );
+
+    let create_bucket_endpoint = metrics::instrument(
+        "create_bucket",
+        warp::any()
+            .and(with_base(client.clone(), &POST_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::header::optional::<String>("content-type"))
+            .and(within_limits())
+            .and(warp::body::bytes())
+            .and_then(crate::backend::create_bucket),
+    );
+
+    let delete_bucket_endpoint = metrics::instrument(
+        "delete_bucket",
+        warp::any()
+            .and(with_base(client.clone(), &DELETE_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::query::<crate::backend::DeleteBucketOptions>())
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(within_limits())
+            .and_then(crate::backend::delete_bucket),
+    );
+
+    let create_object_endpoint = metrics::instrument(
+        "create_object",
+        warp::any()
+            .and(with_base(client.clone(), &POST_METHOD))
+            .and(param())
+            .and(tail())
+            .and(warp::post())
+            .and(warp::header::headers_cloned().map(UploadHeaders::from_headers))
+            .and(within_limits())
+            .and(warp::filters::body::stream())
+            .and_then(crate::backend::create_object),
+    );
+
+    let delete_object_endpoint = metrics::instrument(
+        "delete_object",
+        warp::any()
+            .and(with_base(client.clone(), &DELETE_METHOD))
+            .and(param())
+            .and(tail())
+            .and(warp::delete())
+            .and(within_limits())
+            .and_then(crate::backend::delete_object),
+    );
+
+    let usage_endpoint = metrics::instrument(
+        "usage",
+        warp::any()
+            .and(with_base(client.clone(), &GET_METHOD))
+            .and(warp::filters::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(query_flag("usage"))
+            .and(within_limits())
+            .and_then(crate::backend::get_usage),
+    );
+
+    let head_object_endpoint = metrics::instrument(
+        "head_object",
+        warp::any()
+            .and(with_base(client.clone(), &HEAD_METHOD))
+            .and(param())
+            .and(tail())
+            .and(warp::head())
+            .and(warp::header::headers_cloned().map(DownloadHeaders::from_headers))
+            .and(within_limits())
+            .and_then(crate::backend::head_object),
+    );
+
+    let get_object_endpoint = metrics::instrument(
+        "get_object",
+        warp::any()
+            .and(with_base(client.clone(), &GET_METHOD))
+            .and(param())
+            .and(tail())
+            .and(warp::get())
+            .and(warp::header::headers_cloned().map(DownloadHeaders::from_headers))
+            .and(within_limits())
+            .and_then(crate::backend::get_object),
+    );
+
+    let preflight_endpoint =
+        metrics::instrument("preflight", cors::preflight_endpoint(client.clone()));
+
+    // preflight requests are answered before any route authenticates them,
+    // the webhook, batch delete and form routes share their paths with the bucket routes and have to match first
+    let basic_endpoint = preflight_endpoint
+        .or(create_webhook_endpoint)
+        .or(get_webhooks_endpoint)
+        .or(delete_webhook_endpoint)
+        .or(webhook_deliveries_endpoint)
+        .or(delete_objects_endpoint)
+        .or(create_objects_from_form_endpoint)
+        .or(create_bucket_endpoint)
         .or(delete_bucket_endpoint)
         .or(create_object_endpoint)
         .or(delete_object_endpoint)
+        .or(usage_endpoint)
+        .or(head_object_endpoint)
         .or(get_object_endpoint);
 
-    basic_endpoint.recover(handle_rejection).boxed()
+    cors::with_headers(
+        client,
+        basic_endpoint
+            .recover(handle_rejection)
+            .map(warp::Reply::into_response)
+            .boxed(),
+    )
 }
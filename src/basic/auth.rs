@@ -1,7 +1,12 @@
-use crate::backend::KeyPair;
+use crate::backend::post_policy::PostPolicy;
+use crate::backend::{KeyPair, ADMIN_ORGANISATION};
 use crate::config::Config;
+use crate::metrics;
+use crate::telemetry;
+use crate::tls::ClientCertificate;
 use crate::Client;
 
+use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::{Algorithm, DecodingKey, Validation};
 
 #[derive(Debug, serde::Deserialize)]
@@ -9,7 +14,7 @@ struct SimplePayload {
     sub: String,
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct Payload {
     jti: String,
     sub: String,
@@ -17,6 +22,16 @@ pub struct Payload {
     method: String,
 }
 
+impl Payload {
+    pub fn jti(&self) -> &str {
+        &self.jti
+    }
+
+    pub fn sub(&self) -> &str {
+        &self.sub
+    }
+}
+
 fn b64_decode<T: AsRef<[u8]>>(input: T) -> Result<Vec<u8>, String> {
     base64::decode_config(input, base64::URL_SAFE_NO_PAD).map_err(|e| e.to_string())
 }
@@ -38,7 +53,11 @@ async fn get_secret_from_sub(client: &Client, sub: String) -> Result<KeyPair, St
     if let Some(x) = get_admin_secret(&sub) {
         Ok(x)
     } else {
-        crate::backend::get_keypair_with_access_key(client.clone(), sub).await
+        telemetry::in_span(
+            "keypair_lookup",
+            crate::backend::get_keypair_with_access_key(client.clone(), sub),
+        )
+        .await
     }
 }
 
@@ -52,10 +71,20 @@ fn get_admin_secret(sub: &str) -> Option<KeyPair> {
     }
 }
 
-#[derive(Debug)]
-pub struct Auth {
-    payload: Payload,
-    keypair: KeyPair,
+#[derive(Debug, Clone)]
+pub enum Auth {
+    /// jwt signed with the secret of a keypair, valid for a single method and path
+    Token { payload: Payload, keypair: KeyPair },
+    /// tls client certificate of an organisation in `tls_client_organisations`,
+    /// valid for the buckets configured for it or, without any, all of them
+    Certificate {
+        subject: String,
+        organisation_id: String,
+        buckets: Vec<String>,
+    },
+    /// form upload with a post policy signed with the secret of a keypair to the bucket
+    /// of the policy, its conditions are checked for every file of the form
+    Policy { keypair: KeyPair, bucket: String },
 }
 
 impl Auth {
@@ -63,10 +92,16 @@ impl Auth {
         let auth = match input {
             x if x.starts_with("Bearer ") => &input[7..],
             x if x.starts_with("bearer ") => &input[7..],
-            &_ => return Err(String::from("invalid authorization header")),
+            &_ => {
+                metrics::auth_failure("malformed_header");
+                return Err(String::from("invalid authorization header"));
+            }
         };
-        let sub = get_sub_from_jwt(auth)?;
-        let keypair = get_secret_from_sub(client, sub).await?;
+        let sub =
+            get_sub_from_jwt(auth).inspect_err(|_| metrics::auth_failure("malformed_token"))?;
+        let keypair = get_secret_from_sub(client, sub)
+            .await
+            .inspect_err(|_| metrics::auth_failure("unknown_access_key"))?;
         let mut validation = Validation::new(Algorithm::HS256);
         validation.set_required_spec_claims(&["exp", "sub", "nbf"]);
         let token_message = jsonwebtoken::decode::<Payload>(
@@ -74,23 +109,120 @@ impl Auth {
             &DecodingKey::from_secret(keypair.secret().as_bytes()),
             &validation,
         )
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| {
+            metrics::auth_failure(match e.kind() {
+                ErrorKind::ExpiredSignature => "expired_token",
+                ErrorKind::ImmatureSignature => "immature_token",
+                _ => "invalid_token",
+            });
+            e.to_string()
+        })?;
 
-        Ok(Auth {
+        Ok(Auth::Token {
             payload: token_message.claims,
             keypair,
         })
     }
 
+    /// the certificate was verified during the tls handshake,
+    /// only its organisation is checked here, it never is the admin organisation
+    pub fn from_certificate(certificate: &ClientCertificate) -> Result<Auth, String> {
+        let organisation = match &certificate.organisation {
+            Some(organisation) if !organisation.is_empty() => organisation,
+            _ => {
+                metrics::auth_failure("certificate_without_organisation");
+                return Err(format!(
+                    "client certificate {} has no organisation",
+                    certificate.subject
+                ));
+            }
+        };
+
+        match Config::global().client_certificate_buckets(organisation) {
+            Some(buckets) if organisation != ADMIN_ORGANISATION => Ok(Auth::Certificate {
+                subject: certificate.subject.clone(),
+                organisation_id: organisation.clone(),
+                buckets,
+            }),
+            _ => {
+                metrics::auth_failure("certificate_organisation_not_allowed");
+                Err(format!(
+                    "organisation {} of client certificate {} is not allowed",
+                    organisation, certificate.subject
+                ))
+            }
+        }
+    }
+
+    /// the `access_key`, `policy` and `signature` fields of a form upload
+    pub async fn from_policy(
+        client: &Client,
+        access_key: Option<&str>,
+        policy: &str,
+        signature: Option<&str>,
+    ) -> Result<(Auth, PostPolicy), String> {
+        let (access_key, signature) = match (access_key, signature) {
+            (Some(access_key), Some(signature)) => (access_key, signature),
+            _ => {
+                metrics::auth_failure("malformed_policy");
+                return Err(String::from(
+                    "a policy needs access_key and signature fields",
+                ));
+            }
+        };
+        let keypair = get_secret_from_sub(client, access_key.to_string())
+            .await
+            .map_err(|e| {
+                log::debug!("can not look up access key {}: {}", access_key, e);
+                metrics::auth_failure("unknown_access_key");
+                String::from("unknown access key")
+            })?;
+        let policy = PostPolicy::verify(policy, signature, keypair.secret())
+            .inspect_err(|_| metrics::auth_failure("invalid_policy"))?;
+
+        let bucket = policy.bucket().to_string();
+        Ok((Auth::Policy { keypair, bucket }, policy))
+    }
+
     pub fn validate_request(&self, method: &str, path: &str) -> bool {
-        self.payload.path == path && self.payload.method.to_ascii_uppercase() == method
+        match self {
+            Auth::Token { payload, .. } => {
+                payload.path == path && payload.method.to_ascii_uppercase() == method
+            }
+            Auth::Certificate { buckets, .. } => {
+                let bucket = path.split(['/', '?']).next().unwrap_or_default();
+                buckets.is_empty() || buckets.iter().any(|allowed| allowed == bucket)
+            }
+            Auth::Policy { bucket, .. } => method == "POST" && path == bucket,
+        }
+    }
+
+    pub fn auth(&self) -> Option<&Payload> {
+        match self {
+            Auth::Token { payload, .. } => Some(payload),
+            Auth::Certificate { .. } | Auth::Policy { .. } => None,
+        }
+    }
+
+    /// access key of the jwt or policy, or subject of the client certificate
+    pub fn sub(&self) -> &str {
+        match self {
+            Auth::Token { payload, .. } => payload.sub(),
+            Auth::Certificate { subject, .. } => subject,
+            Auth::Policy { keypair, .. } => keypair.access(),
+        }
     }
 
-    pub fn auth(&self) -> &Payload {
-        &self.payload
+    pub fn jti(&self) -> Option<&str> {
+        self.auth().map(Payload::jti)
     }
 
     pub fn organisation_id(&self) -> &str {
-        self.keypair.organisation_id()
+        match self {
+            Auth::Token { keypair, .. } | Auth::Policy { keypair, .. } => keypair.organisation_id(),
+            Auth::Certificate {
+                organisation_id, ..
+            } => organisation_id,
+        }
     }
 }
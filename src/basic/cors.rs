@@ -0,0 +1,237 @@
+use crate::backend::cors::{self, CorsRule};
+use crate::backend::error::ApiError;
+use crate::backend::{self, Client};
+use crate::config::Config;
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use warp::filters::BoxedFilter;
+use warp::http::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, VARY,
+};
+use warp::http::{Method, StatusCode};
+use warp::path::{Peek, Tail};
+use warp::reply::Response;
+use warp::{Filter, Rejection};
+
+/// how long the rules of a bucket are used before they are looked up again
+const CACHE_TTL: Duration = Duration::from_secs(30);
+/// expired rules are forgotten at most this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+/// buckets whose rules are kept at once, the oldest is forgotten to make room
+const MAX_CACHED_BUCKETS: usize = 10_000;
+
+static BUCKET_RULES: LazyLock<Mutex<BucketRules>> = LazyLock::new(Default::default);
+
+/// rules of existing buckets and when they were looked up
+struct BucketRules {
+    rules: HashMap<String, (Instant, Arc<Vec<CorsRule>>)>,
+    pruned: Instant,
+}
+
+impl Default for BucketRules {
+    fn default() -> BucketRules {
+        BucketRules {
+            rules: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+}
+
+impl BucketRules {
+    fn get(&self, bucket_name: &str) -> Option<Arc<Vec<CorsRule>>> {
+        self.rules
+            .get(bucket_name)
+            .filter(|(fetched, _)| fetched.elapsed() < CACHE_TTL)
+            .map(|(_, rules)| rules.clone())
+    }
+
+    fn insert(&mut self, bucket_name: &str, rules: Arc<Vec<CorsRule>>) {
+        let now = Instant::now();
+        if now.duration_since(self.pruned) > PRUNE_INTERVAL {
+            self.rules
+                .retain(|_, (fetched, _)| now.duration_since(*fetched) < CACHE_TTL);
+            self.pruned = now;
+        }
+        if self.rules.len() >= MAX_CACHED_BUCKETS && !self.rules.contains_key(bucket_name) {
+            let oldest = self
+                .rules
+                .iter()
+                .min_by_key(|(_, (fetched, _))| *fetched)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                self.rules.remove(&oldest);
+            }
+        }
+        self.rules.insert(bucket_name.to_string(), (now, rules));
+    }
+}
+
+/// the rules of the bucket, the server wide default if it has none or doesn't exist yet,
+/// only the rules of existing buckets are cached since any name can be requested
+async fn bucket_rules(client: &Client, bucket_name: &str) -> Arc<Vec<CorsRule>> {
+    if let Some(rules) = BUCKET_RULES.lock().unwrap().get(bucket_name) {
+        return rules;
+    }
+
+    let default_rules = || Arc::new(Config::default_cors_rule().into_iter().collect());
+    match backend::get_bucket_cors(client.clone(), bucket_name.to_string()).await {
+        Ok(Some(rules)) => {
+            let rules = if rules.is_empty() {
+                default_rules()
+            } else {
+                Arc::new(rules)
+            };
+            BUCKET_RULES
+                .lock()
+                .unwrap()
+                .insert(bucket_name, rules.clone());
+            rules
+        }
+        Ok(None) => default_rules(),
+        Err(e) => {
+            log::debug!("can not look up the cors rules of {}: {}", bucket_name, e);
+            default_rules()
+        }
+    }
+}
+
+fn insert_list<'a>(
+    headers: &mut HeaderMap,
+    name: warp::http::header::HeaderName,
+    values: impl IntoIterator<Item = &'a str>,
+) {
+    let values: Vec<&str> = values.into_iter().collect();
+    if values.is_empty() {
+        return;
+    }
+    if let Ok(value) = HeaderValue::from_str(&values.join(", ")) {
+        headers.insert(name, value);
+    }
+}
+
+fn allow_origin(headers: &mut HeaderMap, origin: &str) {
+    if let Ok(origin) = HeaderValue::from_str(origin) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    }
+}
+
+/// answers the `OPTIONS` request a browser sends before a cross-origin request
+async fn preflight(
+    client: Client,
+    bucket_name: String,
+    origin: Option<String>,
+    method: Option<String>,
+    request_headers: Option<String>,
+) -> Result<Response, Rejection> {
+    let (origin, method) = match (origin, method) {
+        (Some(origin), Some(method)) => (origin, method),
+        _ => {
+            return Err(ApiError::InvalidRequest(String::from(
+                "preflight requests need origin and access-control-request-method headers",
+            ))
+            .reject())
+        }
+    };
+    let request_headers = request_headers.unwrap_or_default();
+
+    let rules = bucket_rules(&client, &bucket_name).await;
+    let rule = cors::find_rule(&rules, &origin, &method)
+        .filter(|rule| rule.allows_headers(&request_headers))
+        .ok_or_else(|| {
+            ApiError::CorsNotAllowed(format!(
+                "{} from {} is not allowed for bucket {}",
+                method, origin, bucket_name
+            ))
+            .reject()
+        })?;
+
+    let mut response = Response::default();
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    allow_origin(headers, &origin);
+    insert_list(headers, ACCESS_CONTROL_ALLOW_METHODS, rule.methods());
+    // the requested headers are echoed, a `*` would not cover `authorization`
+    insert_list(
+        headers,
+        ACCESS_CONTROL_ALLOW_HEADERS,
+        request_headers
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty()),
+    );
+    if let Some(max_age) = rule.max_age {
+        headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+
+    Ok(response)
+}
+
+/// `OPTIONS /<bucket>` and `OPTIONS /<bucket>/<object>`, without authentication
+/// since browsers don't send credentials with preflight requests
+pub fn preflight_endpoint(client: Client) -> BoxedFilter<(Response,)> {
+    warp::options()
+        .and(warp::path::param::<String>())
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::header::optional::<String>(
+            "access-control-request-method",
+        ))
+        .and(warp::header::optional::<String>(
+            "access-control-request-headers",
+        ))
+        .and_then(
+            move |bucket_name: String,
+                  _object_name: Tail,
+                  origin: Option<String>,
+                  method: Option<String>,
+                  request_headers: Option<String>| {
+                preflight(client.clone(), bucket_name, origin, method, request_headers)
+            },
+        )
+        .boxed()
+}
+
+/// adds the cors headers to the responses of cross-origin requests a rule of the bucket allows,
+/// error responses included so scripts can read them, every response of a bucket path
+/// varies by origin whether it got them or not
+pub fn with_headers(client: Client, routes: BoxedFilter<(Response,)>) -> BoxedFilter<(Response,)> {
+    warp::path::peek()
+        .and(warp::method())
+        .and(warp::header::optional::<String>("origin"))
+        .and(routes)
+        .and_then(
+            move |path: Peek, method: Method, origin: Option<String>, response: Response| {
+                let client = client.clone();
+                async move {
+                    let mut response = response;
+                    let bucket_name = match path.segments().next() {
+                        Some(bucket_name) => bucket_name.to_string(),
+                        None => return Ok::<_, Rejection>(response),
+                    };
+                    response
+                        .headers_mut()
+                        .append(VARY, HeaderValue::from_static("origin"));
+                    let origin = match origin {
+                        Some(origin) if method != Method::OPTIONS => origin,
+                        _ => return Ok(response),
+                    };
+
+                    let rules = bucket_rules(&client, &bucket_name).await;
+                    if let Some(rule) = cors::find_rule(&rules, &origin, method.as_str()) {
+                        let headers = response.headers_mut();
+                        allow_origin(headers, &origin);
+                        insert_list(
+                            headers,
+                            ACCESS_CONTROL_EXPOSE_HEADERS,
+                            rule.exposed_headers.iter().map(String::as_str),
+                        );
+                    }
+                    Ok(response)
+                }
+            },
+        )
+        .boxed()
+}
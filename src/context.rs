@@ -1,12 +1,33 @@
 use crate::backend::{Client, EMPTY_ORGANISATION};
 use crate::basic::auth::Auth;
-use warp::http::Method;
+use crate::config::{AuthMode, Config};
+use crate::rate_limit::Limits;
+use crate::request_id::RequestId;
+use crate::server::PeerAddress;
+use crate::telemetry;
+use crate::tls::ClientCertificate;
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use warp::http::{Method, Request, Response};
+use warp::hyper::service::{service_fn, Service};
+use warp::hyper::Body;
+use warp::Rejection;
+
+tokio::task_local! {
+    /// the caller of the request and its limits, resolved by the first route trying the request
+    static RESOLVED: RefCell<Option<(Option<Auth>, Limits)>>;
+}
 
 pub struct Context {
     pub client: Client,
     pub auth: Option<Auth>,
     pub method: &'static Method,
     pub path: String,
+    pub remote_addr: Option<SocketAddr>,
+    pub request_id: RequestId,
+    pub limits: Limits,
 }
 
 impl Context {
@@ -14,15 +35,36 @@ impl Context {
     //     Context { client, auth: None }
     // }
 
+    /// every route trying the request gets the same credentials, they are only checked
+    /// and their limits looked up for the first one
     pub async fn from_auth_header(
         client: Client,
         method: &'static Method,
         auth_header: Option<String>,
+        certificate: Option<ClientCertificate>,
+        peer_address: Option<PeerAddress>,
+        request_id: Option<RequestId>,
     ) -> Context {
-        let auth = if let Some(auth) = auth_header {
-            Auth::check_and_create(&client, &auth).await.ok()
-        } else {
-            None
+        let remote_addr = peer_address.map(|peer| peer.0);
+        let resolved = RESOLVED
+            .try_with(|resolved| resolved.borrow().clone())
+            .ok()
+            .flatten();
+        let (auth, limits) = match resolved {
+            Some(resolved) => resolved,
+            None => {
+                // boxed, in debug builds the lookups overflow the stack of the nested routes
+                let resolved = Box::pin(async {
+                    let auth = authenticate(&client, auth_header, certificate).await;
+                    let limits = Limits::resolve(&client, auth.as_ref(), remote_addr).await;
+                    (auth, limits)
+                })
+                .await;
+                RESOLVED
+                    .try_with(|slot| *slot.borrow_mut() = Some(resolved.clone()))
+                    .ok();
+                resolved
+            }
         };
 
         Context {
@@ -30,6 +72,9 @@ impl Context {
             auth,
             method,
             path: String::new(),
+            remote_addr,
+            request_id: request_id.unwrap_or_else(RequestId::generate),
+            limits,
         }
     }
 
@@ -45,14 +90,69 @@ impl Context {
     }
 
     pub fn validate_request(&self) -> bool {
+        self.validate_path(self.method, &self.path)
+    }
+
+    /// whether the caller may make a request to `path`, for requests covering many paths
+    pub fn validate_path(&self, method: &Method, path: &str) -> bool {
         if self.is_logged_in() {
             self.auth
                 .as_ref()
                 .unwrap()
-                .validate_request(self.method.as_str(), &self.path)
+                .validate_request(method.as_str(), path)
         } else {
-            // do we allow public uploads?
-            true
+            Config::global().auth_mode == AuthMode::Optional
         }
     }
 }
+
+/// checks the rate limits of the caller of the request, once a route matched it
+pub fn check_limits() -> Result<(), Rejection> {
+    RESOLVED
+        .try_with(|resolved| match &*resolved.borrow() {
+            Some((_, limits)) => limits.check(),
+            None => Ok(()),
+        })
+        .unwrap_or(Ok(()))
+}
+
+/// a jwt in the authorization header takes precedence over the client certificate
+async fn authenticate(
+    client: &Client,
+    auth_header: Option<String>,
+    certificate: Option<ClientCertificate>,
+) -> Option<Auth> {
+    if let Some(auth) = auth_header {
+        telemetry::in_span(
+            "auth.check_and_create",
+            Auth::check_and_create(client, &auth),
+        )
+        .await
+        .ok()
+    } else if let Some(certificate) = certificate {
+        Auth::from_certificate(&certificate)
+            .map_err(|e| log::debug!("{}", e))
+            .ok()
+    } else {
+        None
+    }
+}
+
+/// scopes the credentials resolved by the routes to the request
+pub fn resolve_once<S>(
+    service: S,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = Infallible,
+    Future = impl Future<Output = Result<Response<Body>, Infallible>> + Send,
+> + Clone
+       + Send
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible> + Clone + Send,
+    S::Future: Send + 'static,
+{
+    service_fn(move |request: Request<Body>| {
+        RESOLVED.scope(RefCell::new(None), service.clone().call(request))
+    })
+}
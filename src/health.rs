@@ -0,0 +1,96 @@
+use crate::backend::{self, Client};
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use warp::http::StatusCode;
+use warp::Filter;
+
+static SETUP_DONE: AtomicBool = AtomicBool::new(false);
+static BACKEND_REACHABLE: AtomicBool = AtomicBool::new(false);
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// what `/readyz` reports, the instance only gets traffic if all of it holds
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    /// indexes and internal collections are created
+    setup: bool,
+    /// the last periodic ping of the backend succeeded
+    backend: bool,
+    /// the shutdown signal was received, open requests are being finished
+    draining: bool,
+}
+
+impl Readiness {
+    fn current() -> Readiness {
+        let setup = SETUP_DONE.load(Ordering::Relaxed);
+        let backend = BACKEND_REACHABLE.load(Ordering::Relaxed);
+        let draining = DRAINING.load(Ordering::Relaxed);
+
+        Readiness {
+            ready: setup && backend && !draining,
+            setup,
+            backend,
+            draining,
+        }
+    }
+}
+
+pub fn setup_done() {
+    SETUP_DONE.store(true, Ordering::Relaxed);
+}
+
+/// from now on `/readyz` fails so the orchestrator stops routing new requests here
+pub fn start_draining() {
+    DRAINING.store(true, Ordering::Relaxed);
+}
+
+/// pings the backend every `interval` until the process exits
+pub async fn run_backend_check(client: Client, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let reachable = match backend::ping(&client).await {
+            Ok(()) => true,
+            Err(e) => {
+                log::debug!("backend ping failed: {}", e);
+                false
+            }
+        };
+
+        let was_reachable = BACKEND_REACHABLE.swap(reachable, Ordering::Relaxed);
+        if was_reachable && !reachable {
+            log::error!("backend is not reachable, reporting not ready");
+        } else if !was_reachable && reachable {
+            log::info!("backend is reachable");
+        }
+    }
+}
+
+/// `/healthz` answers as long as the process runs, `/readyz` only while it should get traffic
+pub fn health_endpoint() -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::json(&serde_json::json!({"status": "ok"})));
+
+    let readyz = warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| {
+            let readiness = Readiness::current();
+            let status = if readiness.ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            warp::reply::with_status(warp::reply::json(&readiness), status)
+        });
+
+    healthz
+        .map(warp::Reply::into_response)
+        .or(readyz.map(warp::Reply::into_response))
+        .unify()
+        .boxed()
+}
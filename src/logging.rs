@@ -0,0 +1,46 @@
+use crate::config::LogFormat;
+use crate::request_id;
+
+use std::io::Write;
+
+/// logs to stderr filtered by `RUST_LOG`, lines written while a request
+/// is handled carry its id
+pub fn init(format: LogFormat) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+
+    match format {
+        LogFormat::Text => builder.format(|f, record| {
+            let level_style = f.default_level_style(record.level());
+            let level = level_style.value(format!("{:<5}", record.level()));
+            let mut style = f.style();
+            let target = style.set_bold(true).value(record.target());
+
+            match request_id::current() {
+                Some(request_id) => writeln!(
+                    f,
+                    " {} {} [{}] > {}",
+                    level,
+                    target,
+                    request_id,
+                    record.args()
+                ),
+                None => writeln!(f, " {} {} > {}", level, target, record.args()),
+            }
+        }),
+        LogFormat::Json => builder.format(|f, record| {
+            let line = serde_json::json!({
+                "timestamp": f.timestamp_millis().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+                "request_id": request_id::current().map(|id| id.to_string()),
+            });
+            writeln!(f, "{}", line)
+        }),
+    };
+
+    builder.init();
+}
@@ -2,10 +2,20 @@ use warp::Filter;
 
 pub type GeneralResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+pub mod admin;
 pub mod backend;
 pub mod basic;
 pub mod config;
 pub mod context;
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod rate_limit;
+pub mod request_id;
+pub mod server;
+pub mod telemetry;
+pub mod throttle;
+pub mod tls;
 
 use backend::Client;
 use config::Config;
@@ -13,21 +23,92 @@ use context::Context;
 
 #[tokio::main]
 async fn main() -> GeneralResult<()> {
-    pretty_env_logger::init();
-    let config = Config::global();
+    let config = match Config::init() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    logging::init(config.log_format);
+    if let Err(error) = telemetry::setup() {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 
-    let client = backend::make_client().await?;
+    let client = match backend::make_client().await {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("can not connect to the backend: {}", error);
+            std::process::exit(1);
+        }
+    };
     backend::setup(&client).await?;
-    let basic_route = warp::path("basic").and(basic::basic_endpoint(client));
-    let routes = warp::path("api").and(basic_route);
+    health::setup_done();
+    tokio::spawn(health::run_backend_check(
+        client.clone(),
+        std::time::Duration::from_secs(config.health_check_interval),
+    ));
 
-    let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(config.address, async {
-        tokio::signal::ctrl_c().await.ok();
+    if config.scrub_interval > 0 {
+        tokio::spawn(backend::run_scrubber(
+            client.clone(),
+            std::time::Duration::from_secs(config.scrub_interval),
+        ));
+    }
+
+    if config.sweep_interval > 0 {
+        tokio::spawn(backend::run_sweeper(
+            client.clone(),
+            std::time::Duration::from_secs(config.sweep_interval),
+        ));
+    }
+
+    if let Some(metrics_address) = config.metrics_address {
+        // for scrapers on an internal network, the server stops with the process
+        let metrics_route = warp::path("metrics")
+            .and(warp::path::end())
+            .and(warp::get())
+            .map(metrics::render);
+        tokio::spawn(async move {
+            if let Err(e) = server::serve(
+                warp::service(metrics_route),
+                metrics_address,
+                std::future::pending(),
+            )
+            .await
+            {
+                log::error!("can not serve metrics on {}: {}", metrics_address, e);
+            }
+        });
+    }
+
+    let basic_route = warp::path("basic").and(basic::basic_endpoint(client.clone()));
+    let admin_route = warp::path("admin").and(admin::admin_endpoint(client.clone()));
+    let metrics_route = admin::metrics_endpoint(client);
+    let routes = warp::path("api")
+        .and(basic_route.or(admin_route))
+        .or(metrics_route)
+        .or(health::health_endpoint())
+        .recover(basic::handle_rejection);
+
+    let shutdown = async {
+        server::shutdown_signal().await;
+        log::info!("draining for {} seconds", config.drain_period);
+        health::start_draining();
+        tokio::time::sleep(std::time::Duration::from_secs(config.drain_period)).await;
         log::info!("shutting down");
-    });
-    log::info!("running on {}", addr);
+    };
 
-    server.await;
+    let service = request_id::assign(rate_limit::report(telemetry::trace_requests(
+        context::resolve_once(warp::service(routes)),
+    )));
+    if config.tls_enabled() {
+        tls::serve(service, config.address, shutdown).await?;
+    } else {
+        server::serve(service, config.address, shutdown).await?;
+    }
+    telemetry::shutdown().await;
 
     Ok(())
 }
@@ -0,0 +1,222 @@
+use crate::basic::rejection_status;
+use crate::telemetry;
+
+use futures::stream::{Stream, StreamExt};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::hyper::body::{Body, Bytes};
+use warp::reply::Response;
+use warp::{Buf, Filter, Rejection, Reply};
+
+static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "file_storage_http_requests_total",
+        "requests by route and status code",
+        &["route", "status"]
+    )
+    .unwrap()
+});
+
+static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "file_storage_http_request_duration_seconds",
+        "time until the response headers are ready, by route and status code",
+        &["route", "status"]
+    )
+    .unwrap()
+});
+
+static UPLOADED_BYTES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "file_storage_uploaded_bytes_total",
+        "bytes received in object uploads"
+    )
+    .unwrap()
+});
+
+static DOWNLOADED_BYTES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "file_storage_downloaded_bytes_total",
+        "bytes sent in object downloads"
+    )
+    .unwrap()
+});
+
+static AUDIT_RECORDS_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "file_storage_audit_records_dropped_total",
+        "audit records dropped because the writer fell behind"
+    )
+    .unwrap()
+});
+
+static STREAMS_IN_FLIGHT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "file_storage_streams_in_flight",
+        "object uploads and downloads currently streaming",
+        &["direction"]
+    )
+    .unwrap()
+});
+
+static BACKEND_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "file_storage_backend_operation_duration_seconds",
+        "duration of backend operations by operation and result",
+        &["operation", "result"]
+    )
+    .unwrap()
+});
+
+static AUTH_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "file_storage_auth_failures_total",
+        "rejected credentials and requests by reason",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+static REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "file_storage_rejections_total",
+        "rejections turned into responses by type",
+        &["type"]
+    )
+    .unwrap()
+});
+
+/// the metrics in the prometheus text format
+pub fn render() -> Response {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        log::error!("failed to encode metrics: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    warp::reply::with_header(buffer, "content-type", encoder.format_type()).into_response()
+}
+
+fn record_request(route: &str, status: StatusCode, start: Instant) {
+    let status = status.as_str();
+    HTTP_REQUESTS.with_label_values(&[route, status]).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[route, status])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// counts the requests handled by `filter` under the name `route`,
+/// including the ones its handler rejects, and names the request span after it
+pub fn instrument<F, R>(route: &'static str, filter: F) -> BoxedFilter<(Response,)>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(
+            filter
+                .map(|reply: R| Ok::<_, Rejection>(reply.into_response()))
+                .or_else(|rejection: Rejection| async move {
+                    match rejection_status(&rejection) {
+                        Some(_) => Ok((Err(rejection),)),
+                        None => Err(rejection),
+                    }
+                }),
+        )
+        .and_then(
+            move |start: Instant, result: Result<Response, Rejection>| async move {
+                let status = match &result {
+                    Ok(response) => response.status(),
+                    Err(rejection) => {
+                        rejection_status(rejection).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                };
+                record_request(route, status, start);
+                telemetry::record_route(route);
+                result
+            },
+        )
+        .boxed()
+}
+
+/// times a call into the backend
+pub async fn time_operation<T, E>(
+    operation: &'static str,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = future.await;
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    BACKEND_DURATION
+        .with_label_values(&[operation, outcome])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+pub fn auth_failure(reason: &'static str) {
+    AUTH_FAILURES.with_label_values(&[reason]).inc();
+}
+
+pub fn rejection(kind: &'static str) {
+    REJECTIONS.with_label_values(&[kind]).inc();
+}
+
+pub fn audit_record_dropped() {
+    AUDIT_RECORDS_DROPPED.inc();
+}
+
+/// decrements the gauge of its direction when the stream is dropped
+struct InFlight(&'static str);
+
+impl InFlight {
+    fn start(direction: &'static str) -> InFlight {
+        STREAMS_IN_FLIGHT.with_label_values(&[direction]).inc();
+        InFlight(direction)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        STREAMS_IN_FLIGHT.with_label_values(&[self.0]).dec();
+    }
+}
+
+/// counts the bytes of an upload body while it is read
+pub fn count_upload<S, B>(stream: S) -> impl Stream<Item = Result<B, warp::Error>>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let in_flight = InFlight::start("upload");
+    stream.map(move |chunk| {
+        let _ = &in_flight;
+        if let Ok(chunk) = &chunk {
+            UPLOADED_BYTES.inc_by(chunk.remaining() as u64);
+        }
+        chunk
+    })
+}
+
+/// counts the bytes of a response body while it is sent
+pub fn count_download(response: Response) -> Response {
+    let (parts, body) = response.into_parts();
+    let in_flight = InFlight::start("download");
+    let body = body.map(move |chunk: Result<Bytes, warp::hyper::Error>| {
+        let _ = &in_flight;
+        if let Ok(chunk) = &chunk {
+            DOWNLOADED_BYTES.inc_by(chunk.len() as u64);
+        }
+        chunk
+    });
+
+    Response::from_parts(parts, Body::wrap_stream(body))
+}
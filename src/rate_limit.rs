@@ -0,0 +1,452 @@
+use crate::backend::error::ApiError;
+use crate::backend::{self, Client, ADMIN_ORGANISATION};
+use crate::basic::auth::Auth;
+use crate::config::Config;
+
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::header::HeaderValue;
+use warp::http::{Request, Response};
+use warp::hyper::body::Bytes;
+use warp::hyper::service::{service_fn, Service};
+use warp::hyper::Body;
+use warp::{Buf, Rejection};
+
+/// how long the limits of an organisation are used before they are looked up again
+const ORGANISATION_CACHE_TTL: Duration = Duration::from_secs(30);
+/// buckets that are full again are forgotten at most this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+static REQUEST_BUCKETS: LazyLock<Mutex<Buckets>> = LazyLock::new(Default::default);
+static BYTE_BUCKETS: LazyLock<Mutex<Buckets>> = LazyLock::new(Default::default);
+static ORGANISATION_LIMITS: LazyLock<Mutex<OrganisationLimits>> = LazyLock::new(Default::default);
+
+tokio::task_local! {
+    static CHECKED: Cell<Option<Decision>>;
+}
+
+/// limits of a keypair, an organisation or an anonymous client ip, `None` or 0 means unlimited,
+/// each limit allows bursts of `rate_limit_burst_seconds` worth of traffic
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests_per_second: Option<f64>,
+    /// bytes of object uploads and downloads per second, concurrent streams are throttled
+    /// to share it and requests are rejected while more was transferred
+    pub bytes_per_second: Option<u64>,
+}
+
+/// who a bucket of tokens belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subject {
+    KeyPair(String),
+    Organisation(String),
+    Address(IpAddr),
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// negative while transferred bytes are paid off
+    tokens: f64,
+    rate: f64,
+    capacity: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64) -> TokenBucket {
+        TokenBucket {
+            tokens: capacity,
+            rate,
+            capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// keeps the tokens when the limit was changed
+    fn set_limit(&mut self, rate: f64, capacity: f64) {
+        self.rate = rate;
+        self.capacity = capacity;
+        self.tokens = self.tokens.min(capacity);
+    }
+
+    /// seconds until `amount` tokens are available
+    fn wait(&self, amount: f64) -> f64 {
+        ((amount - self.tokens) / self.rate).max(0.0)
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.capacity
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            limit: self.capacity as u64,
+            remaining: self.tokens.max(0.0) as u64,
+            reset: self.wait(self.capacity).ceil() as u64,
+        }
+    }
+}
+
+struct Buckets {
+    buckets: HashMap<Subject, Arc<Mutex<TokenBucket>>>,
+    pruned: Instant,
+}
+
+impl Default for Buckets {
+    fn default() -> Buckets {
+        Buckets {
+            buckets: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+}
+
+impl Buckets {
+    fn get(&mut self, subject: &Subject, rate: f64, capacity: f64) -> Arc<Mutex<TokenBucket>> {
+        let now = Instant::now();
+        if now.duration_since(self.pruned) > PRUNE_INTERVAL {
+            // a full bucket behaves like a new one, unless a stream is still using it
+            self.buckets.retain(|_, bucket| {
+                let mut locked = bucket.lock().unwrap();
+                locked.refill(now);
+                Arc::strong_count(bucket) > 1 || !locked.is_full()
+            });
+            self.pruned = now;
+        }
+
+        let bucket = self
+            .buckets
+            .entry(subject.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(TokenBucket::new(rate, capacity))));
+        {
+            let mut locked = bucket.lock().unwrap();
+            if locked.rate != rate || locked.capacity != capacity {
+                locked.set_limit(rate, capacity);
+            }
+        }
+        bucket.clone()
+    }
+}
+
+/// the limits of organisations as they were looked up
+struct OrganisationLimits {
+    limits: HashMap<String, (Instant, RateLimit)>,
+    pruned: Instant,
+}
+
+impl Default for OrganisationLimits {
+    fn default() -> OrganisationLimits {
+        OrganisationLimits {
+            limits: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+}
+
+impl OrganisationLimits {
+    fn get(&self, organisation_id: &str) -> Option<RateLimit> {
+        self.limits
+            .get(organisation_id)
+            .filter(|(fetched, _)| fetched.elapsed() < ORGANISATION_CACHE_TTL)
+            .map(|(_, limit)| *limit)
+    }
+
+    fn insert(&mut self, organisation_id: &str, limit: RateLimit) {
+        let now = Instant::now();
+        if now.duration_since(self.pruned) > PRUNE_INTERVAL {
+            // organisations that made no request for a while would be looked up again anyway
+            self.limits
+                .retain(|_, (fetched, _)| now.duration_since(*fetched) < ORGANISATION_CACHE_TTL);
+            self.pruned = now;
+        }
+        self.limits
+            .insert(organisation_id.to_string(), (now, limit));
+    }
+}
+
+/// outcome of the rate limit check of a request
+#[derive(Debug, Clone, Copy)]
+struct Decision {
+    /// `None` if the request is not subject to a request limit
+    status: Option<Status>,
+    /// seconds until the request can be retried if it was rejected
+    retry_after: Option<u64>,
+}
+
+impl Decision {
+    fn result(&self) -> Result<(), Rejection> {
+        match self.retry_after {
+            Some(retry_after) => Err(ApiError::TooManyRequests(retry_after).reject()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// what the `RateLimit-*` headers report, for the most exhausted request limit
+#[derive(Debug, Clone, Copy)]
+struct Status {
+    limit: u64,
+    remaining: u64,
+    /// seconds until the limit is fully available again
+    reset: u64,
+}
+
+fn capacity(rate: f64) -> f64 {
+    (rate * Config::global().rate_limit_burst_seconds as f64).max(1.0)
+}
+
+/// the limits of an organisation, looked up at most every `ORGANISATION_CACHE_TTL`
+async fn organisation_limit(client: &Client, organisation_id: &str) -> RateLimit {
+    if let Some(limit) = ORGANISATION_LIMITS.lock().unwrap().get(organisation_id) {
+        return limit;
+    }
+
+    let limit =
+        match backend::get_organisation_rate_limit(client.clone(), organisation_id.to_string())
+            .await
+        {
+            Ok(limit) => limit.unwrap_or_else(Config::default_organisation_rate_limit),
+            Err(e) => {
+                log::debug!(
+                    "can not look up the rate limit of {}: {}",
+                    organisation_id,
+                    e
+                );
+                Config::default_organisation_rate_limit()
+            }
+        };
+    ORGANISATION_LIMITS
+        .lock()
+        .unwrap()
+        .insert(organisation_id, limit);
+
+    limit
+}
+
+/// the rate limits a request counts against
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    subjects: Vec<(Subject, RateLimit)>,
+}
+
+impl Limits {
+    /// keypairs count against their own and their organisation's limits,
+    /// anonymous requests against the limit of their client ip, the admin organisation is not limited
+    pub async fn resolve(
+        client: &Client,
+        auth: Option<&Auth>,
+        remote_addr: Option<SocketAddr>,
+    ) -> Limits {
+        let mut subjects = Vec::new();
+        match auth {
+            Some(auth) if auth.organisation_id() == ADMIN_ORGANISATION => (),
+            Some(auth) => {
+                if let Auth::Token { keypair, .. } | Auth::Policy { keypair, .. } = auth {
+                    subjects.push((
+                        Subject::KeyPair(keypair.access().to_string()),
+                        keypair
+                            .rate_limit()
+                            .unwrap_or_else(Config::default_keypair_rate_limit),
+                    ));
+                }
+                let organisation_id = auth.organisation_id();
+                subjects.push((
+                    Subject::Organisation(organisation_id.to_string()),
+                    organisation_limit(client, organisation_id).await,
+                ));
+            }
+            None => {
+                if let Some(address) = remote_addr {
+                    subjects.push((
+                        Subject::Address(address.ip()),
+                        Config::anonymous_rate_limit(),
+                    ));
+                }
+            }
+        }
+
+        Limits { subjects }
+    }
+
+    /// the bandwidth limits of the request, by who they belong to
+    pub fn byte_rates(&self) -> impl Iterator<Item = (&Subject, u64)> {
+        self.subjects.iter().filter_map(|(subject, limit)| {
+            Some((subject, limit.bytes_per_second.filter(|rate| *rate > 0)?))
+        })
+    }
+
+    fn request_buckets(&self) -> Vec<Arc<Mutex<TokenBucket>>> {
+        let mut buckets = REQUEST_BUCKETS.lock().unwrap();
+        self.subjects
+            .iter()
+            .filter_map(|(subject, limit)| {
+                let rate = limit.requests_per_second.filter(|rate| *rate > 0.0)?;
+                Some(buckets.get(subject, rate, capacity(rate)))
+            })
+            .collect()
+    }
+
+    fn byte_buckets(&self) -> Vec<Arc<Mutex<TokenBucket>>> {
+        let mut buckets = BYTE_BUCKETS.lock().unwrap();
+        self.byte_rates()
+            .map(|(subject, rate)| {
+                let rate = rate as f64;
+                buckets.get(subject, rate, capacity(rate))
+            })
+            .collect()
+    }
+
+    /// takes a token from every request limit, rejects the request without taking any
+    /// if one of them is exhausted or more bytes were transferred than a bandwidth limit allows
+    pub fn check(&self) -> Result<(), Rejection> {
+        // a request rejected by one route is tried by the following ones, it is only counted once
+        let decision = match CHECKED.try_with(Cell::get).ok().flatten() {
+            Some(decision) => decision,
+            None => {
+                let decision = self.decide();
+                CHECKED.try_with(|slot| slot.set(Some(decision))).ok();
+                decision
+            }
+        };
+
+        decision.result()
+    }
+
+    /// counts the request once more, for callers that are only known after the body was read
+    pub fn check_again(&self) -> Result<(), Rejection> {
+        let decision = self.decide();
+        CHECKED.try_with(|slot| slot.set(Some(decision))).ok();
+
+        decision.result()
+    }
+
+    fn decide(&self) -> Decision {
+        let request_buckets = self.request_buckets();
+        let byte_buckets = self.byte_buckets();
+        let now = Instant::now();
+
+        // always locked in the order of `subjects`, so concurrent checks can't deadlock
+        let mut requests: Vec<_> = request_buckets
+            .iter()
+            .map(|bucket| bucket.lock().unwrap())
+            .collect();
+        let mut bytes: Vec<_> = byte_buckets
+            .iter()
+            .map(|bucket| bucket.lock().unwrap())
+            .collect();
+        requests
+            .iter_mut()
+            .chain(bytes.iter_mut())
+            .for_each(|bucket| bucket.refill(now));
+
+        let wait = requests
+            .iter()
+            .map(|bucket| bucket.wait(1.0))
+            .chain(bytes.iter().map(|bucket| bucket.wait(0.0)))
+            .fold(0.0, f64::max);
+        let retry_after = if wait > 0.0 {
+            Some(wait.ceil() as u64)
+        } else {
+            for bucket in requests.iter_mut() {
+                bucket.tokens -= 1.0;
+            }
+            None
+        };
+
+        Decision {
+            status: requests
+                .iter()
+                .map(|bucket| bucket.status())
+                .min_by_key(|status| status.remaining),
+            retry_after,
+        }
+    }
+
+    /// takes the bytes of an upload body from the bandwidth limits while it is read
+    pub fn count_upload<S, B, E>(&self, stream: S) -> impl Stream<Item = Result<B, E>>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: Buf,
+    {
+        let buckets = self.byte_buckets();
+        stream.map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                take_bytes(&buckets, chunk.remaining());
+            }
+            chunk
+        })
+    }
+
+    /// takes the bytes of a response body from the bandwidth limits while it is sent
+    pub fn count_download(&self, response: warp::reply::Response) -> warp::reply::Response {
+        let buckets = self.byte_buckets();
+        if buckets.is_empty() {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = body.map(move |chunk: Result<Bytes, warp::hyper::Error>| {
+            if let Ok(chunk) = &chunk {
+                take_bytes(&buckets, chunk.len());
+            }
+            chunk
+        });
+
+        warp::reply::Response::from_parts(parts, Body::wrap_stream(body))
+    }
+}
+
+/// bytes already transferred are never refused, the next request waits until they are paid off
+fn take_bytes(buckets: &[Arc<Mutex<TokenBucket>>], amount: usize) {
+    let now = Instant::now();
+    for bucket in buckets {
+        let mut bucket = bucket.lock().unwrap();
+        bucket.refill(now);
+        bucket.tokens -= amount as f64;
+    }
+}
+
+/// adds the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers
+/// to responses of requests that counted against a request limit
+pub fn report<S>(
+    service: S,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = Infallible,
+    Future = impl Future<Output = Result<Response<Body>, Infallible>> + Send,
+> + Clone
+       + Send
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible> + Clone + Send,
+    S::Future: Send + 'static,
+{
+    service_fn(move |request: Request<Body>| {
+        let response = service.clone().call(request);
+
+        CHECKED.scope(Cell::new(None), async move {
+            let mut response = response.await?;
+            if let Some(status) = CHECKED.with(Cell::get).and_then(|decision| decision.status) {
+                let headers = response.headers_mut();
+                headers.insert("ratelimit-limit", HeaderValue::from(status.limit));
+                headers.insert("ratelimit-remaining", HeaderValue::from(status.remaining));
+                headers.insert("ratelimit-reset", HeaderValue::from(status.reset));
+            }
+            Ok(response)
+        })
+    })
+}
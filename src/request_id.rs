@@ -0,0 +1,91 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use std::convert::Infallible;
+use std::future::Future;
+use warp::http::header::HeaderValue;
+use warp::http::{Request, Response};
+use warp::hyper::service::{service_fn, Service};
+use warp::hyper::Body;
+
+pub const HEADER: &str = "x-request-id";
+/// longer ids from clients are replaced by a generated one
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// id of a request, taken from its `x-request-id` header or generated,
+/// added to the extensions of the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> RequestId {
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        RequestId(hex::encode(bytes))
+    }
+
+    /// only printable ascii is accepted so the id can't break log lines
+    fn from_header(value: &HeaderValue) -> Option<RequestId> {
+        let value = value.to_str().ok()?;
+        if value.is_empty()
+            || value.len() > MAX_LENGTH
+            || !value.bytes().all(|byte| byte.is_ascii_graphic())
+        {
+            return None;
+        }
+
+        Some(RequestId(value.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// the id of the request the current task is handling
+pub fn current() -> Option<RequestId> {
+    CURRENT.try_with(RequestId::clone).ok()
+}
+
+/// gives every request an id, returns it in the response
+/// and makes it the `current` id while the request is handled
+pub fn assign<S>(
+    service: S,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = Infallible,
+    Future = impl Future<Output = Result<Response<Body>, Infallible>> + Send,
+> + Clone
+       + Send
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible> + Clone + Send,
+    S::Future: Send + 'static,
+{
+    service_fn(move |mut request: Request<Body>| {
+        let request_id = request
+            .headers()
+            .get(HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::generate);
+        request.extensions_mut().insert(request_id.clone());
+        let response = CURRENT.scope(request_id.clone(), service.clone().call(request));
+
+        async move {
+            let mut response = response.await?;
+            if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+                response.headers_mut().insert(HEADER, value);
+            }
+            Ok(response)
+        }
+    })
+}
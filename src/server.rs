@@ -0,0 +1,68 @@
+use crate::GeneralResult;
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use warp::http::{Request, Response};
+use warp::hyper::server::conn::AddrStream;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Server};
+
+/// address of the client, added to the extensions of every request of the connection
+#[derive(Debug, Clone, Copy)]
+pub struct PeerAddress(pub SocketAddr);
+
+/// completes on ctrl-c or, on unix, SIGTERM
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                log::warn!("can not listen for SIGTERM: {}", e);
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
+/// serves `service` over plain http until `shutdown` completes
+pub async fn serve<S>(
+    service: S,
+    address: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> GeneralResult<()>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let make_service = make_service_fn(move |stream: &AddrStream| {
+        let peer_address = PeerAddress(stream.remote_addr());
+        let service = service.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(peer_address);
+                service.clone().call(request)
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)?.serve(make_service);
+    log::info!("running on {}", server.local_addr());
+    server.with_graceful_shutdown(shutdown).await?;
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use crate::config::{Config, TracingExporter};
+use crate::request_id::RequestId;
+use crate::GeneralResult;
+
+use futures::future::BoxFuture;
+use futures::stream::{Stream, StreamExt};
+use opentelemetry::global;
+use opentelemetry::trace::{FutureExt, SpanKind, Status, TraceContextExt, TraceError, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::future::Future;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use warp::http::{Request, Response};
+use warp::hyper::service::{service_fn, Service};
+use warp::hyper::Body;
+
+const TRACER: &str = "file-storage";
+
+/// installs the exporter from the config, without one every span is a no-op
+pub fn setup() -> GeneralResult<()> {
+    let config = Config::global();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let builder = match config.tracing_exporter {
+        TracingExporter::None => return Ok(()),
+        TracingExporter::Stdout => TracerProvider::builder().with_simple_exporter(StdoutExporter),
+        TracingExporter::Otlp => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(&config.tracing_otlp_endpoint)
+                .build_span_exporter()
+                .map_err(|e| format!("can not create the otlp exporter: {}", e))?;
+            TracerProvider::builder()
+                .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        }
+    };
+    let resource = Resource::new([KeyValue::new(
+        "service.name",
+        config.tracing_service_name.clone(),
+    )]);
+    global::set_tracer_provider(
+        builder
+            .with_config(opentelemetry_sdk::trace::config().with_resource(resource))
+            .build(),
+    );
+
+    Ok(())
+}
+
+/// exports the spans that are still buffered
+pub async fn shutdown() {
+    tokio::task::spawn_blocking(global::shutdown_tracer_provider)
+        .await
+        .ok();
+}
+
+/// starts a server span for every request, continuing the trace of its `traceparent` header,
+/// the handler runs with the span as its current context
+pub fn trace_requests<S>(
+    service: S,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = Infallible,
+    Future = impl Future<Output = Result<Response<Body>, Infallible>> + Send,
+> + Clone
+       + Send
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible> + Clone + Send,
+    S::Future: Send + 'static,
+{
+    service_fn(move |request: Request<Body>| {
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        });
+        let mut attributes = vec![
+            KeyValue::new("http.method", request.method().to_string()),
+            KeyValue::new("http.target", request.uri().path().to_string()),
+        ];
+        if let Some(request_id) = request.extensions().get::<RequestId>() {
+            attributes.push(KeyValue::new("request_id", request_id.to_string()));
+        }
+        let tracer = global::tracer(TRACER);
+        let span = tracer
+            .span_builder(format!("{} {}", request.method(), request.uri().path()))
+            .with_kind(SpanKind::Server)
+            .with_attributes(attributes)
+            .start_with_context(&tracer, &parent);
+        let context = parent.with_span(span);
+        let response = service.clone().call(request).with_context(context.clone());
+
+        async move {
+            let response = response.await?;
+            let span = context.span();
+            span.set_attribute(KeyValue::new(
+                "http.status_code",
+                i64::from(response.status().as_u16()),
+            ));
+            if response.status().is_server_error() {
+                span.set_status(Status::error(response.status().to_string()));
+            }
+            Ok(response)
+        }
+    })
+}
+
+/// names the request span after the route that handled it
+pub fn record_route(route: &'static str) {
+    let context = Context::current();
+    let span = context.span();
+    span.update_name(route);
+    span.set_attribute(KeyValue::new("http.route", route));
+}
+
+/// a span below the current one, it ends when the returned context is dropped
+pub fn child_span(name: &'static str) -> Context {
+    let span = global::tracer(TRACER).start(name);
+    Context::current_with_span(span)
+}
+
+/// runs `future` in a span below the current one, errors are recorded on the span
+pub async fn in_span<T, E: Display>(
+    name: &'static str,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let context = child_span(name);
+    let result = future.with_context(context.clone()).await;
+    if let Err(e) = &result {
+        context.span().set_status(Status::error(e.to_string()));
+    }
+    result
+}
+
+/// keeps the span of `context` open until the stream is consumed or dropped
+pub fn hold_span<S: Stream>(context: Context, stream: S) -> impl Stream<Item = S::Item> {
+    stream.map(move |item| {
+        let _ = &context;
+        item
+    })
+}
+
+fn micros(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros())
+        .unwrap_or_default()
+}
+
+/// writes every finished span as a json line to stdout
+#[derive(Debug)]
+struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let mut stdout = std::io::stdout().lock();
+        let result = batch.into_iter().try_for_each(|span| {
+            let attributes: serde_json::Map<String, serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|attribute| {
+                    (
+                        attribute.key.to_string(),
+                        attribute.value.to_string().into(),
+                    )
+                })
+                .collect();
+            let line = serde_json::json!({
+                "trace_id": span.span_context.trace_id().to_string(),
+                "span_id": span.span_context.span_id().to_string(),
+                "parent_span_id": span.parent_span_id.to_string(),
+                "name": span.name,
+                "kind": format!("{:?}", span.span_kind),
+                "start_us": micros(span.start_time) as u64,
+                "duration_us": micros(span.end_time).saturating_sub(micros(span.start_time)) as u64,
+                "status": format!("{:?}", span.status),
+                "attributes": attributes,
+            });
+            writeln!(stdout, "{}", line)
+        });
+
+        Box::pin(std::future::ready(
+            result.map_err(|e| TraceError::from(e.to_string())),
+        ))
+    }
+}
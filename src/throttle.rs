@@ -0,0 +1,124 @@
+use crate::config::Config;
+use crate::context::Context;
+use crate::rate_limit::Subject;
+
+use futures::future::Either;
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::Duration;
+use tokio::time::Instant;
+
+static SHARES: LazyLock<Mutex<HashMap<Group, Weak<Share>>>> = LazyLock::new(Default::default);
+
+/// streams that divide a byte rate between them
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Group {
+    Connection(SocketAddr),
+    Limit(Subject),
+}
+
+#[derive(Debug)]
+struct Share {
+    bytes_per_second: AtomicU64,
+    streams: AtomicUsize,
+}
+
+impl Share {
+    /// the part of the rate one of the current streams gets
+    fn stream_rate(&self) -> f64 {
+        self.bytes_per_second.load(Ordering::Relaxed) as f64
+            / self.streams.load(Ordering::Relaxed).max(1) as f64
+    }
+}
+
+/// counts a stream of its share until it is dropped
+#[derive(Debug)]
+struct Membership(Arc<Share>);
+
+impl Membership {
+    fn join(group: Group, bytes_per_second: u64) -> Membership {
+        let mut shares = SHARES.lock().unwrap();
+        let share = match shares.get(&group).and_then(Weak::upgrade) {
+            Some(share) => share,
+            None => {
+                shares.retain(|_, share| share.strong_count() > 0);
+                let share = Arc::new(Share {
+                    bytes_per_second: AtomicU64::new(bytes_per_second),
+                    streams: AtomicUsize::new(0),
+                });
+                shares.insert(group, Arc::downgrade(&share));
+                share
+            }
+        };
+        // a changed limit applies to the streams that are already running as well
+        share
+            .bytes_per_second
+            .store(bytes_per_second, Ordering::Relaxed);
+        share.streams.fetch_add(1, Ordering::Relaxed);
+
+        Membership(share)
+    }
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        self.0.streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// paces an object stream to the smallest share of the byte rates it is subject to,
+/// the bandwidth limits of the request and `connection_bytes_per_second`
+#[derive(Debug)]
+pub struct Throttle {
+    memberships: Vec<Membership>,
+}
+
+impl Throttle {
+    pub fn new(context: &Context) -> Throttle {
+        let mut memberships: Vec<_> = context
+            .limits
+            .byte_rates()
+            .map(|(subject, rate)| Membership::join(Group::Limit(subject.clone()), rate))
+            .collect();
+        if let (Some(address), Some(rate)) = (
+            context.remote_addr,
+            Config::global().connection_bytes_per_second,
+        ) {
+            memberships.push(Membership::join(Group::Connection(address), rate));
+        }
+
+        Throttle { memberships }
+    }
+
+    fn stream_rate(&self) -> f64 {
+        self.memberships
+            .iter()
+            .map(|membership| membership.0.stream_rate())
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// delays every item until the ones before it were transferred at the stream's rate,
+    /// the rate is recomputed for every item as streams start and end
+    pub fn stream<S: Stream>(
+        self,
+        stream: S,
+        len: fn(&S::Item) -> usize,
+    ) -> impl Stream<Item = S::Item> {
+        let mut next = Instant::now();
+        stream.then(move |item| {
+            if self.memberships.is_empty() {
+                return Either::Left(futures::future::ready(item));
+            }
+
+            let start = next.max(Instant::now());
+            next = start + Duration::from_secs_f64(len(&item) as f64 / self.stream_rate());
+            Either::Right(async move {
+                tokio::time::sleep_until(start).await;
+                item
+            })
+        })
+    }
+}
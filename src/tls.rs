@@ -0,0 +1,270 @@
+use crate::config::Config;
+use crate::server::PeerAddress;
+use crate::GeneralResult;
+
+use futures::stream::StreamExt;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::http::{Request, Response};
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Server};
+
+/// connections still in the handshake, further connections wait in the backlog
+const MAX_HANDSHAKES: usize = 128;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// identity of a client that connected with a certificate signed by `tls_client_ca_file`,
+/// added to the extensions of every request of the connection
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub subject: String,
+    /// the organisation (`O`) of the subject
+    pub organisation: Option<String>,
+}
+
+impl ClientCertificate {
+    fn from_der(der: &[u8]) -> Option<ClientCertificate> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+        let subject = certificate.subject();
+        let organisation = subject
+            .iter_organization()
+            .next()
+            .and_then(|organisation| organisation.as_str().ok())
+            .map(String::from);
+
+        Some(ClientCertificate {
+            subject: subject.to_string(),
+            organisation,
+        })
+    }
+}
+
+/// the files the server config is built from and when they were last changed
+struct CertificateFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<PathBuf>,
+    client_auth_required: bool,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl CertificateFiles {
+    fn from_config(config: &Config) -> Option<CertificateFiles> {
+        let mut files = CertificateFiles {
+            cert: config.tls_cert_file.clone()?,
+            key: config.tls_key_file.clone()?,
+            client_ca: config.tls_client_ca_file.clone(),
+            client_auth_required: config.tls_client_auth_required,
+            modified: Vec::new(),
+        };
+        files.modified = files.modified_times();
+        Some(files)
+    }
+
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// returns true once for every change of the files
+    fn changed(&mut self) -> bool {
+        let modified = self.modified_times();
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+
+    fn load(&self) -> GeneralResult<ServerConfig> {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in read_certificates(client_ca)? {
+                    roots.add(&certificate).map_err(|e| {
+                        format!("invalid certificate in {}, {}", client_ca.display(), e)
+                    })?;
+                }
+                if self.client_auth_required {
+                    builder
+                        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+                } else {
+                    builder.with_client_cert_verifier(
+                        AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+                    )
+                }
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut server_config = builder
+            .with_single_cert(read_certificates(&self.cert)?, read_key(&self.key)?)
+            .map_err(|e| format!("invalid certificate or key, {}", e))?;
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(server_config)
+    }
+}
+
+fn read_certificates(path: &Path) -> GeneralResult<Vec<Certificate>> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let certificates = rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if certificates.is_empty() {
+        return Err(format!("{}: no certificates found", path.display()).into());
+    }
+
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> GeneralResult<PrivateKey> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut reader = std::io::BufReader::new(file);
+
+    while let Some(item) =
+        rustls_pemfile::read_one(&mut reader).map_err(|e| format!("{}: {}", path.display(), e))?
+    {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+
+    Err(format!("{}: no private key found", path.display()).into())
+}
+
+/// rebuilds the server config whenever one of its files changes,
+/// invalid files are logged and the previous config stays in use
+async fn reload(
+    mut files: CertificateFiles,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if !files.changed() {
+            continue;
+        }
+
+        match files.load() {
+            Ok(server_config) => {
+                *current.write().unwrap() = Arc::new(server_config);
+                log::info!("reloaded tls certificates from {}", files.cert.display());
+            }
+            Err(e) => log::error!("failed to reload tls certificates: {}", e),
+        }
+    }
+}
+
+/// serves `service` over https until `shutdown` completes
+pub async fn serve<S>(
+    service: S,
+    address: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> GeneralResult<()>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let config = Config::global();
+    let files = CertificateFiles::from_config(config)
+        .ok_or("tls_cert_file and tls_key_file are required")?;
+    let current = Arc::new(RwLock::new(Arc::new(files.load()?)));
+    if config.tls_reload_interval > 0 {
+        tokio::spawn(reload(
+            files,
+            current.clone(),
+            Duration::from_secs(config.tls_reload_interval),
+        ));
+    }
+
+    let listener = TcpListener::bind(address).await?;
+    log::info!("running on https://{}", listener.local_addr()?);
+
+    let incoming = futures::stream::unfold(listener, |listener| async move {
+        let connection = listener.accept().await;
+        Some((connection, listener))
+    })
+    .filter_map(|connection| async move {
+        match connection {
+            Ok((stream, _)) => Some(stream),
+            Err(e) => {
+                log::warn!("failed to accept connection: {}", e);
+                // usually out of file descriptors, give connections time to close
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                None
+            }
+        }
+    })
+    .map(move |stream| {
+        let acceptor = TlsAcceptor::from(current.read().unwrap().clone());
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+    })
+    .buffer_unordered(MAX_HANDSHAKES)
+    .filter_map(|handshake| async move {
+        match handshake {
+            Ok(Ok(stream)) => Some(Ok::<_, std::io::Error>(stream)),
+            Ok(Err(e)) => {
+                log::debug!("tls handshake failed: {}", e);
+                None
+            }
+            Err(_) => {
+                log::debug!("tls handshake timed out");
+                None
+            }
+        }
+    });
+
+    let make_service = make_service_fn(move |stream: &TlsStream<TcpStream>| {
+        let peer_address = stream.get_ref().0.peer_addr().ok().map(PeerAddress);
+        let certificate = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .and_then(|certificate| ClientCertificate::from_der(&certificate.0));
+        let service = service.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                if let Some(certificate) = &certificate {
+                    request.extensions_mut().insert(certificate.clone());
+                }
+                if let Some(peer_address) = peer_address {
+                    request.extensions_mut().insert(peer_address);
+                }
+                service.clone().call(request)
+            }))
+        }
+    });
+
+    Server::builder(warp::hyper::server::accept::from_stream(incoming))
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await?;
+
+    Ok(())
+}